@@ -1 +1,40 @@
-pub mod xma;
\ No newline at end of file
+use serde::Serialize;
+use tauri::{Manager, Runtime, Window};
+
+use crate::error::Error;
+
+pub mod pmp_to_ttmp;
+pub mod ttmp_to_pmp;
+pub mod xma;
+
+/// Progress of converting between modpack formats.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum ConvertProgress {
+    SettingUp,
+    HashingFiles {
+        current: usize,
+        total: usize,
+    },
+    ExtractingFiles {
+        current: usize,
+        total: usize,
+    },
+    EncodingFiles {
+        current: usize,
+        total: usize,
+    },
+    CreatingArchive,
+    Verifying,
+    Done,
+}
+
+impl ConvertProgress {
+    pub const EVENT: &'static str = "convert-progress";
+
+    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> Result<(), Error> {
+        // this only fails if the window is gone, so there's no one to report to
+        window.emit_all(Self::EVENT, self)
+            .map_err(|_| Error::Cancelled)
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tauri::{Runtime, Window};
+use ttmp::mpd_encoder::{FileInfo, MpdEncoder};
+use zip::ZipArchive;
+
+use crate::{pmp, verify, Group, GroupManipulations, ModInfo, OptionItem, OptionManipulations, SelectionType, TtmpOptions};
+use crate::converters::ConvertProgress;
+use crate::create_ttmp::{self, PreparedManifest};
+use crate::delta::{GroupKind, PenumbraGroup, PenumbraStandardOption, PenumbraStandardOptionSimple};
+use crate::error::Error;
+use crate::meta::Manipulation;
+use crate::pmp::PenumbraMeta;
+
+/// Convert a Penumbra modpack into a TexTools wizard modpack without going
+/// through Heliosphere. Each group gets its own page unless the options say
+/// otherwise, manipulations become TexTools metadata files and group images
+/// are shown on each of the group's options. File swaps, priorities, IMC
+/// groups and manipulations TexTools has no metadata for can't be carried
+/// over, so packs using them are rejected.
+pub fn pmp_to_ttmp_inner<R: Runtime>(window: Window<R>, path: &str, output: Option<String>, options: TtmpOptions) -> anyhow::Result<()> {
+    ConvertProgress::SettingUp.emit(&window)?;
+
+    let path = Path::new(path);
+    let destination = output.map(PathBuf::from)
+        .unwrap_or_else(|| path.with_extension("ttmp2"));
+
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let meta: PenumbraMeta = {
+        let file = zip.by_name("meta.json")
+            .map_err(|_| Error::InvalidArchive("pmp is missing meta.json".into()))?;
+        serde_json::from_value(pmp::read_json(file, "meta.json")?)
+            .context("invalid pmp: invalid meta.json")?
+    };
+    let default: PenumbraStandardOptionSimple = {
+        let file = zip.by_name("default_mod.json")
+            .map_err(|_| Error::MissingEntry("default_mod.json".into()))?;
+        serde_json::from_value(pmp::read_json(file, "default_mod.json")?)
+            .context("invalid pmp: invalid default_mod.json")?
+    };
+
+    let mut group_paths: Vec<String> = zip.file_names()
+        .filter(|name| pmp::is_group(name))
+        .map(ToOwned::to_owned)
+        .collect();
+    group_paths.sort_unstable();
+
+    let entries = pmp::entry_names(&zip);
+
+    let mut penumbra_groups: Vec<PenumbraGroup> = Vec::with_capacity(group_paths.len());
+    for name in &group_paths {
+        let file = zip.by_name(name)
+            .with_context(|| format!("could not get {name}"))?;
+        let group = serde_json::from_value(pmp::read_json(file, name)?)
+            .with_context(|| format!("invalid pmp: invalid group {name}"))?;
+        penumbra_groups.push(group);
+    }
+
+    // every option, along with the group and option names TexTools will know
+    // it by
+    let mut all_options: Vec<(Option<&str>, Option<&str>, &PenumbraStandardOptionSimple)> = vec![(None, None, &default)];
+    let mut groups = Vec::with_capacity(penumbra_groups.len());
+    let mut images = Vec::new();
+    for group in &penumbra_groups {
+        let (options, selection_type) = match &group.kind {
+            GroupKind::Single { options } => (options, SelectionType::Single),
+            GroupKind::Multi { options } => (options, SelectionType::Multi),
+            GroupKind::Imc { .. } => {
+                return Err(Error::UnsupportedFormat(format!("group {} is an IMC group, which TexTools does not support", group.name)).into());
+            }
+        };
+
+        if group.priority != 0 {
+            return Err(Error::UnsupportedFormat(format!("group {} has a priority, which TexTools does not support", group.name)).into());
+        }
+
+        for option in options {
+            if option.priority != 0 {
+                return Err(Error::UnsupportedFormat(format!("{} / {} has a priority, which TexTools does not support", group.name, option.name)).into());
+            }
+
+            all_options.push((Some(group.name.as_str()), Some(option.name.as_str()), &option.simple));
+        }
+
+        // TexTools only has images on options, so every option shows the
+        // group's image. Images that aren't in the pack are left out.
+        let image = group.image.as_deref()
+            .filter(|image| !image.is_empty())
+            .and_then(|image| entries.get(&pmp::normalise(image)));
+        if let Some(entry) = image {
+            if !images.contains(entry) {
+                images.push(entry.clone());
+            }
+        }
+
+        groups.push(Group {
+            name: group.name.clone(),
+            selection_type,
+            options: options.iter().map(|option| option_item(option, image)).collect(),
+            page: None,
+            default_settings: group.default_settings,
+        });
+    }
+
+    for (group, option, simple) in &all_options {
+        if !simple.file_swaps.is_empty() {
+            return Err(Error::UnsupportedFormat(format!("{} uses file swaps, which TexTools does not support", describe(*group, *option))).into());
+        }
+
+        for manipulation in &simple.manipulations {
+            if serde_json::from_value::<Manipulation>(manipulation.clone()).is_err() {
+                let kind = manipulation.get("Type")
+                    .and_then(serde_json::Value::as_str)
+                    .unwrap_or("unknown");
+                return Err(Error::UnsupportedFormat(format!(
+                    "{} uses {kind} manipulations, which TexTools does not support",
+                    describe(*group, *option),
+                )).into());
+            }
+        }
+    }
+
+    let mut image_entries = Vec::with_capacity(images.len());
+    for entry in images {
+        let mut data = Vec::new();
+        zip.by_name(&entry)?.read_to_end(&mut data)?;
+        image_entries.push((entry, data));
+    }
+
+    // hash every file, so each content is only encoded once
+    let mut to_hash: Vec<&String> = Vec::new();
+    for (_, _, option) in &all_options {
+        for local in option.files.values() {
+            let entry = entries.get(&pmp::normalise(local))
+                .ok_or_else(|| Error::MissingEntry(local.clone()))?;
+            to_hash.push(entry);
+        }
+    }
+    to_hash.sort_unstable();
+    to_hash.dedup();
+
+    ConvertProgress::HashingFiles {
+        current: 0,
+        total: to_hash.len(),
+    }.emit(&window)?;
+
+    let mut hashes = HashMap::with_capacity(to_hash.len());
+    let mut hash_to_entry = HashMap::new();
+    for (i, entry) in to_hash.iter().enumerate() {
+        let hash = verify::content_hash(zip.by_name(entry)?)?;
+        hash_to_entry.entry(hash.clone()).or_insert(*entry);
+        hashes.insert(*entry, hash);
+
+        ConvertProgress::HashingFiles {
+            current: i + 1,
+            total: to_hash.len(),
+        }.emit(&window)?;
+    }
+
+    let mut files: HashMap<String, Vec<_>> = HashMap::new();
+    let mut manipulations = Vec::new();
+    for (group, option, simple) in &all_options {
+        for (game_path, local) in &simple.files {
+            let hash = &hashes[&entries[&pmp::normalise(local)]];
+            files.entry(hash.clone())
+                .or_default()
+                .push((group.map(ToOwned::to_owned), option.map(ToOwned::to_owned), game_path.clone(), None));
+        }
+
+        if !simple.manipulations.is_empty() {
+            manipulations.push(GroupManipulations {
+                name: group.map(ToOwned::to_owned),
+                options: vec![OptionManipulations {
+                    name: option.map(ToOwned::to_owned),
+                    manipulations: simple.manipulations.clone(),
+                }],
+            });
+        }
+    }
+
+    let info = ModInfo {
+        name: meta.name,
+        author: meta.author,
+        version: meta.version,
+        description: meta.description,
+        url: meta.website,
+    };
+    let PreparedManifest { manifest, meta_files, expected } = create_ttmp::prepare_manifest(
+        info,
+        &groups,
+        &files,
+        &manipulations,
+        &options,
+    )?;
+
+    let total = files.len();
+    ConvertProgress::EncodingFiles {
+        current: 0,
+        total,
+    }.emit(&window)?;
+
+    let mut encoder = MpdEncoder::new(tempfile::tempfile()?, manifest, None);
+    let mut staging = tempfile::tempfile()?;
+    for (i, (hash, uses)) in files.into_iter().enumerate() {
+        staging.rewind()?;
+        staging.set_len(0)?;
+        std::io::copy(&mut zip.by_name(hash_to_entry[&hash])?, &mut staging)?;
+        let size = staging.stream_position()? as usize;
+
+        for (group, option, game_path, _) in uses {
+            staging.rewind()?;
+            let info = FileInfo {
+                group,
+                option,
+                game_path,
+            };
+
+            create_ttmp::encode_file(&mut encoder, info, size, &mut staging)?;
+        }
+
+        ConvertProgress::EncodingFiles {
+            current: i + 1,
+            total,
+        }.emit(&window)?;
+    }
+
+    ConvertProgress::CreatingArchive.emit(&window)?;
+    let temp = create_ttmp::write_ttmp(encoder, meta_files, image_entries, &destination)?;
+
+    ConvertProgress::Verifying.emit(&window)?;
+    verify::verify_ttmp(BufReader::new(temp.reopen()?), &expected)?;
+    temp.persist(&destination)?;
+
+    ConvertProgress::Done.emit(&window)?;
+
+    Ok(())
+}
+
+fn option_item(option: &PenumbraStandardOption, image: Option<&String>) -> OptionItem {
+    OptionItem {
+        name: option.name.clone(),
+        image_path: image.cloned(),
+        description: option.description.clone().filter(|description| !description.is_empty()),
+        game_paths: option.simple.files.keys().cloned().collect(),
+    }
+}
+
+fn describe(group: Option<&str>, option: Option<&str>) -> String {
+    match (group, option) {
+        (Some(group), Some(option)) => format!("{group} / {option}"),
+        _ => "the default option".into(),
+    }
+}
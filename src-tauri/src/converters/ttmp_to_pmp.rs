@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tauri::{Runtime, Window};
+use ttmp::model::{ManifestKind, ModOption, SelectionType as TtmpSelectionType};
+use ttmp::ttmp_extractor::TtmpExtractor;
+use zip::{CompressionMethod, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::{meta, pmp, verify};
+use crate::converters::ConvertProgress;
+use crate::deduplicate::temp_file_for;
+use crate::delta::{GroupKind, PenumbraGroup, PenumbraStandardOption, PenumbraStandardOptionSimple};
+use crate::error::Error;
+use crate::pmp::PenumbraMeta;
+
+/// A group being built up from the wizard pages.
+struct GroupBuilder {
+    name: String,
+    multi: bool,
+    default_settings: u32,
+    image: Option<String>,
+    options: Vec<PenumbraStandardOption>,
+}
+
+/// What the last extracted file turned into, for reuse by files that share
+/// its data.
+enum Extracted {
+    File(String),
+    Manipulations(Vec<Value>),
+}
+
+/// Convert a TexTools modpack into a Penumbra one. Files are extracted to
+/// `files/<group>/<option>/<game path>`, metadata files become manipulations
+/// and each wizard group becomes a Penumbra group. Penumbra only has images
+/// on groups, so a group uses the first image of its options. Groups that
+/// share a name with an earlier one get a number added, since Penumbra needs
+/// group names to be unique.
+pub fn ttmp_to_pmp_inner<R: Runtime>(window: Window<R>, path: &str, output: Option<String>) -> anyhow::Result<()> {
+    ConvertProgress::SettingUp.emit(&window)?;
+
+    let path = Path::new(path);
+    let destination = output.map(PathBuf::from)
+        .unwrap_or_else(|| path.with_extension("pmp"));
+
+    let file = File::open(path)?;
+    let extractor = TtmpExtractor::new(file)
+        .map_err(|e| Error::InvalidArchive(e.to_string()))?;
+
+    let (meta, pages) = match extractor.manifest() {
+        ManifestKind::V2(pack) => {
+            let meta = PenumbraMeta {
+                file_version: pmp::FILE_VERSION,
+                name: pack.name.clone(),
+                author: pack.author.clone(),
+                description: pack.description.clone().unwrap_or_default(),
+                version: pack.version.clone(),
+                website: pack.url.clone().unwrap_or_default(),
+                mod_tags: Vec::new(),
+            };
+
+            (meta, pack.mod_pack_pages.as_deref().unwrap_or_default())
+        }
+        ManifestKind::V1(mods) => {
+            let entry = mods.iter().find_map(|mod_| mod_.mod_pack_entry.as_ref());
+            let meta = PenumbraMeta {
+                file_version: pmp::FILE_VERSION,
+                name: entry.map(|entry| entry.name.clone())
+                    .or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                    .unwrap_or_default(),
+                author: entry.map(|entry| entry.author.clone()).unwrap_or_default(),
+                version: entry.map(|entry| entry.version.clone()).unwrap_or_default(),
+                ..Default::default()
+            };
+
+            (meta, Default::default())
+        }
+    };
+
+    // lay out the groups in the order TexTools shows them
+    let mut pages: Vec<_> = pages.iter().collect();
+    pages.sort_by_key(|page| page.page_index);
+
+    let mut groups: Vec<GroupBuilder> = Vec::new();
+    // the files only name their group and option, and wizard pages can
+    // repeat names, so keep every option a pair of names could mean
+    let mut option_indices: HashMap<(&str, &str), Vec<(usize, usize, &ModOption)>> = HashMap::new();
+    for group in pages.iter().flat_map(|page| &page.mod_groups) {
+        let multi = matches!(group.selection_type, TtmpSelectionType::Multi);
+        if multi && group.option_list.len() > 32 {
+            return Err(Error::UnsupportedFormat(format!(
+                "multi-choice group {} has {} options, but Penumbra allows at most 32",
+                group.group_name,
+                group.option_list.len(),
+            )).into());
+        }
+
+        let default_settings = if multi {
+            group.option_list.iter()
+                .enumerate()
+                .filter(|(_, option)| option.is_checked)
+                .fold(0, |bits, (i, _)| bits | 1 << i)
+        } else {
+            group.option_list.iter()
+                .position(|option| option.is_checked)
+                .unwrap_or_default() as u32
+        };
+
+        for (i, option) in group.option_list.iter().enumerate() {
+            option_indices.entry((group.group_name.as_str(), option.name.as_str()))
+                .or_default()
+                .push((groups.len(), i, option));
+        }
+
+        let mut name = group.group_name.clone();
+        let mut copy = 1;
+        while groups.iter().any(|existing| existing.name == name) {
+            copy += 1;
+            name = format!("{} ({copy})", group.group_name);
+        }
+
+        groups.push(GroupBuilder {
+            name,
+            multi,
+            default_settings,
+            image: group.option_list.iter()
+                .find_map(|option| option.image_path.as_deref())
+                .filter(|image| !image.is_empty())
+                .map(|image| image.replace('\\', "/")),
+            options: group.option_list.iter()
+                .map(|option| PenumbraStandardOption {
+                    name: option.name.clone(),
+                    description: option.description.clone(),
+                    priority: 0,
+                    simple: empty_option(),
+                })
+                .collect(),
+        });
+    }
+
+    let mut default = empty_option();
+
+    let temp = temp_file_for(&destination)?;
+    let mut new_zip = ZipWriter::new(temp);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let files = extractor.all_files_sorted();
+    let total = files.len();
+    let mut zip = extractor.zip().borrow_mut();
+    let mut data = zip.by_name("TTMPD.mpd")
+        .map_err(|_| Error::MissingEntry("TTMPD.mpd".into()))?;
+    let mut staging = tempfile::tempfile()?;
+
+    ConvertProgress::ExtractingFiles {
+        current: 0,
+        total,
+    }.emit(&window)?;
+
+    // the hash of every file, to check the new archive against
+    let mut expected = HashMap::new();
+    let mut assigned = HashSet::new();
+    let mut written = HashSet::new();
+    let mut last_offset = None;
+    let mut last: Option<(Extracted, String)> = None;
+
+    for (i, file) in files.into_iter().enumerate() {
+        let game_path = file.file.full_path.clone();
+        let target = match (file.group, file.option) {
+            (Some(group), Some(option)) => option_indices.get(&(group, option)).and_then(|candidates| {
+                // use the first option that lists this file and hasn't been
+                // given it yet
+                let lists_file = |option: &ModOption| option.mods_jsons.iter()
+                    .any(|mod_| mod_.full_path == game_path && mod_.mod_offset == file.file.mod_offset);
+                candidates.iter()
+                    .filter(|(_, _, option)| lists_file(option))
+                    .map(|&(group, option, _)| (group, option))
+                    .find(|&(group, option)| !assigned.contains(&(group, option, game_path.clone())))
+                    .or_else(|| candidates.first().map(|&(group, option, _)| (group, option)))
+            }),
+            _ => None,
+        };
+        let (option, folder, key) = match target {
+            Some((group, option)) => {
+                assigned.insert((group, option, game_path.clone()));
+
+                let group = &mut groups[group];
+                let option = &mut group.options[option];
+                let folder = format!("files/{}/{}", pmp::sanitise(&group.name), pmp::sanitise(&option.name));
+                let key = (Some(group.name.clone()), Some(option.name.clone()), game_path.clone());
+                (&mut option.simple, folder, key)
+            }
+            None => (&mut default, "files".to_string(), (None, None, game_path.clone())),
+        };
+
+        // deduplicated files share their data
+        if Some(file.file.mod_offset) != last_offset {
+            last_offset = Some(file.file.mod_offset);
+
+            staging.rewind()?;
+            staging.set_len(0)?;
+            TtmpExtractor::extract_one_into(&file, &mut data, &mut staging)?;
+            staging.rewind()?;
+            let hash = verify::content_hash(&mut staging)?;
+            staging.rewind()?;
+
+            let extracted = if game_path.ends_with(".meta") || game_path.ends_with(".rgsp") {
+                let mut bytes = Vec::new();
+                staging.read_to_end(&mut bytes)?;
+                let manipulations = meta::manipulations(&game_path, &bytes)?.iter()
+                    .map(serde_json::to_value)
+                    .collect::<Result<_, _>>()?;
+                Extracted::Manipulations(manipulations)
+            } else {
+                let mut archive_path = format!("{folder}/{game_path}");
+                // the same path can come up again for different data
+                while !written.insert(pmp::normalise(&archive_path)) {
+                    archive_path = format!("{folder}/{}/{game_path}", written.len());
+                }
+
+                new_zip.start_file(archive_path.as_str(), options)?;
+                std::io::copy(&mut staging, &mut new_zip)?;
+                Extracted::File(archive_path.replace('/', "\\"))
+            };
+
+            last = Some((extracted, hash));
+        }
+
+        match &last {
+            Some((Extracted::File(archive_path), hash)) => {
+                option.files.insert(game_path, archive_path.clone());
+                expected.insert(key, hash.clone());
+            }
+            Some((Extracted::Manipulations(manipulations), _)) => {
+                option.manipulations.extend(manipulations.iter().cloned());
+            }
+            None => {}
+        }
+
+        ConvertProgress::ExtractingFiles {
+            current: i + 1,
+            total,
+        }.emit(&window)?;
+    }
+
+    drop(data);
+
+    ConvertProgress::CreatingArchive.emit(&window)?;
+
+    // bring the option images along, leaving out any the modpack doesn't
+    // actually contain
+    let entries = pmp::entry_names(&*zip);
+    let mut images = HashSet::new();
+    for group in &mut groups {
+        let image = match &group.image {
+            Some(image) => image.clone(),
+            None => continue,
+        };
+
+        let entry = match entries.get(&pmp::normalise(&image)) {
+            Some(entry) => entry,
+            None => {
+                group.image = None;
+                continue;
+            }
+        };
+
+        if images.insert(image.clone()) {
+            let mut file = zip.by_name(entry)?;
+            new_zip.start_file(image, SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+            std::io::copy(&mut file, &mut new_zip)?;
+        }
+    }
+
+    drop(zip);
+    drop(extractor);
+
+    new_zip.start_file("meta.json", options)?;
+    serde_json::to_writer_pretty(&mut new_zip, &meta)?;
+
+    new_zip.start_file("default_mod.json", options)?;
+    serde_json::to_writer_pretty(&mut new_zip, &default)?;
+
+    for (i, group) in groups.into_iter().enumerate() {
+        let name = format!("group_{:03}_{}.json", i + 1, pmp::sanitise(&group.name).to_lowercase());
+        let group = PenumbraGroup {
+            version: Some(0),
+            name: group.name,
+            description: Some(String::new()),
+            priority: 0,
+            default_settings: group.default_settings,
+            image: group.image,
+            kind: if group.multi {
+                GroupKind::Multi { options: group.options }
+            } else {
+                GroupKind::Single { options: group.options }
+            },
+        };
+
+        new_zip.start_file(name, options)?;
+        serde_json::to_writer_pretty(&mut new_zip, &group)?;
+    }
+
+    let temp = new_zip.finish()?;
+
+    ConvertProgress::Verifying.emit(&window)?;
+    verify::verify_pmp(temp.reopen()?, &expected)?;
+
+    temp.persist(&destination)?;
+
+    ConvertProgress::Done.emit(&window)?;
+
+    Ok(())
+}
+
+fn empty_option() -> PenumbraStandardOptionSimple {
+    PenumbraStandardOptionSimple {
+        version: None,
+        files: HashMap::new(),
+        file_swaps: HashMap::new(),
+        manipulations: Vec::new(),
+    }
+}
@@ -1,15 +1,130 @@
-use std::{borrow::Cow, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+use std::{borrow::Cow, sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}}};
 
 use anyhow::{Context as _, Result};
 use html5ever::{local_name, ns, namespace_url, tendril::TendrilSink, QualName};
 use lol_html::{html_content::{ContentType, EndTag}, DocumentContentHandlers, RewriteStrSettings};
 use markup5ever_rcdom::{RcDom, Handle, NodeData};
+use reqwest::cookie::Jar;
 use scraper::{Html, Selector, Element};
+use serde::Serialize;
+use tokio::sync::RwLock;
 use url::Url;
 
-pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
+use crate::error::Error;
+
+const XMA_HOST: &str = "www.xivmodarchive.com";
+const TITLE_SELECTOR: &str = ".jumbotron > .row h1";
+
+/// Tells sessions apart, so a request that finishes after the session was
+/// replaced doesn't update the new one.
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A cookie session with XIV Mod Archive, kept around so the anonymous login
+/// only has to happen once while the app is open.
+pub struct XmaSession {
+    id: usize,
+    /// Shares the cookie jar with every clone.
+    client: reqwest::Client,
+    /// Whether the jar holds a session at all, anonymous or not.
+    has_session: bool,
+    /// Whether the session is one the user logged in with.
+    logged_in: bool,
+}
+
+impl XmaSession {
+    /// Start a new session, optionally with the session cookie of a logged
+    /// in account (`name=value`) so age-gated mods can be read.
+    pub fn new(session_cookie: Option<&str>) -> Result<Self> {
+        let jar = Arc::new(Jar::default());
+        if let Some(cookie) = session_cookie {
+            let url = Url::parse(&format!("https://{XMA_HOST}/")).unwrap();
+            jar.add_cookie_str(&format!("{}; Domain=xivmodarchive.com; Path=/", cookie.trim()), &url);
+        }
+
+        let client = reqwest::Client::builder()
+            .cookie_provider(jar)
+            .build()
+            .context("could not create http client")?;
+
+        Ok(Self {
+            id: SESSIONS.fetch_add(1, Ordering::SeqCst),
+            client,
+            has_session: session_cookie.is_some(),
+            logged_in: session_cookie.is_some(),
+        })
+    }
+}
+
+/// Download a page, getting a new anonymous session if there isn't one or
+/// the old one has expired. The lock is only held to look at and update the
+/// session, never across a request.
+async fn fetch(session: &RwLock<XmaSession>, url: &Url) -> Result<String> {
+    loop {
+        let (id, client, has_session, logged_in) = {
+            let session = session.read().await;
+            (session.id, session.client.clone(), session.has_session, session.logged_in)
+        };
+
+        let fresh = !has_session;
+        if fresh {
+            // the cookies this sets end up in the jar
+            client.get(format!("https://{XMA_HOST}/anon_login"))
+                .send()
+                .await
+                .context("could not log in to xma anonymously")?
+                .error_for_status()?;
+
+            set_has_session(session, id, true).await;
+        }
+
+        let resp = client.get(url.clone())
+            .send()
+            .await
+            .context("could not download xma page")?
+            .error_for_status()?;
+        let final_url = resp.url().clone();
+        let page = resp.text()
+            .await
+            .context("could not get xma page as text")?;
+
+        if !is_login_wall(&final_url, &page) {
+            return Ok(page);
+        }
+
+        if fresh || logged_in {
+            return Err(Error::LoginRequired(url.to_string()).into());
+        }
+
+        set_has_session(session, id, false).await;
+    }
+}
+
+async fn set_has_session(session: &RwLock<XmaSession>, id: usize, has_session: bool) {
+    let mut session = session.write().await;
+    if session.id == id {
+        session.has_session = has_session;
+    }
+}
+
+/// XMA shows a login form instead of the mod when the page needs an account
+/// (or an age check) that the session doesn't have.
+fn is_login_wall(url: &Url, page: &str) -> bool {
+    if url.path().starts_with("/login") {
+        return true;
+    }
+
+    let dom = Html::parse_document(page);
+    let title_s = Selector::parse(TITLE_SELECTOR).unwrap();
+    let password_s = Selector::parse("input[type='password']").unwrap();
+
+    dom.select(&title_s).next().is_none() && dom.select(&password_s).next().is_some()
+}
+
+/// Scrape a mod page on XIV Mod Archive into the details needed to prefill
+/// an upload.
+pub async fn xma_to_markdown(session: &RwLock<XmaSession>, url: String) -> Result<ConvertedInfo> {
     // validate the url
-    let url = Url::parse(&url).context("could not parse url")?;
+    let mut url = Url::parse(&url).context("could not parse url")?;
     if !matches!(url.host_str(), Some("xivmodarchive.com" | "www.xivmodarchive.com")) {
         anyhow::bail!("not an XMA url");
     }
@@ -18,33 +133,17 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
         anyhow::bail!("not an XMA url that points to a mod");
     }
 
-    let client = reqwest::Client::new();
-    let resp = client.get("https://www.xivmodarchive.com/anon_login")
-        .send()
-        .await?;
-    let cookies = resp.headers()
-        .get_all("Cookie")
-        .iter()
-        .cloned()
-        .collect::<Vec<_>>();
+    // the session cookies are only sent to the host that set them
+    url.set_host(Some(XMA_HOST)).context("could not normalise xma url")?;
 
     // download the page
-    let resp = client
-        .get(url)
-        // FIXME
-        // .header("Cookie", cookies)
-        .send()
-        .await
-        .context("could not download xma page")?
-        .text()
-        .await
-        .context("could not get xma page as text")?;
+    let resp = fetch(session, &url).await?;
 
     // parse the html into a dom
     let dom = Html::parse_document(&resp);
 
     // selectors
-    let title_s = Selector::parse(".jumbotron > .row h1").unwrap();
+    let title_s = Selector::parse(TITLE_SELECTOR).unwrap();
     let author_link_s = Selector::parse(".jumbotron > .row p.lead > a").unwrap();
     let info_s = Selector::parse("#info").unwrap();
     let tag_link_s = Selector::parse("a[href ^= '/search?tags=']").unwrap();
@@ -78,7 +177,6 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
     // get the info section
     let info = dom.select(&info_s).next()
         .context("missing info section")?;
-    println!("{}", info.inner_html());
 
     let brs = AtomicUsize::default();
     let in_list = AtomicBool::default();
@@ -94,7 +192,6 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
 
         // heading contents should not end in colons
         lol_html::text!("p.lead", |t| {
-            println!("p.lead: {:?}", t.as_str());
             let text = t.as_str().trim_end_matches(':').to_string();
             t.replace(
                 &text,
@@ -109,10 +206,8 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
             el.remove_and_keep_content();
             // start a paragraph
             el.prepend("<p>", ContentType::Html);
-            println!("starting p");
             if let Some(handlers) = el.end_tag_handlers() {
                 handlers.push(Box::new(|end_tag: &mut EndTag| {
-                    println!("ending p");
                     end_tag.before("</p>", ContentType::Html);
                     Ok(())
                 }));
@@ -166,7 +261,6 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
 
             let prev = brs.swap(0, Ordering::SeqCst);
             if prev >= 2 {
-                println!("ending p and starting new p");
                 el.before("</p><p>", ContentType::Html);
             }
 
@@ -180,12 +274,10 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
             if !trimmed.is_empty() {
                 let prev = brs.swap(0, Ordering::SeqCst);
                 if prev >= 2 {
-                    println!("ending p and starting new p");
                     t.before("</p><p>", ContentType::Html);
                 }
             }
 
-            println!("doc: {:?}", t.as_str());
             Ok(())
         });
 
@@ -199,9 +291,6 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
     )
         .context("could not rewrite xma html")?;
 
-    println!("rewritten");
-    println!("{rewritten}");
-
     let parser = html5ever::parse_fragment(
         RcDom::default(),
         html5ever::ParseOpts::default(),
@@ -287,8 +376,9 @@ pub async fn xma_to_markdown<'a>(url: String) -> Result<ConvertedInfo> {
     })
 }
 
-// #[derive(SimpleObject)]
-    pub struct ConvertedInfo {
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertedInfo {
     pub title: String,
     pub nsfw: bool,
     pub nsfl: bool,
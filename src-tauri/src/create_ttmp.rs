@@ -1,183 +1,305 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File as StdFile;
-use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
+use blake3::Hasher as Blake3;
+use reqwest::header::CONTENT_LENGTH;
 use serde::Serialize;
 use tauri::{Manager, Runtime};
 use tauri::window::Window;
+use tempfile::NamedTempFile;
 use tokio::fs::File as TokioFile;
 use tokio::io::AsyncSeekExt;
 use ttmp::model::{ManifestKind, ModGroup, ModOption, ModPack, ModPackPage, SimpleMod};
 use ttmp::mpd_encoder::{FileInfo, MpdEncoder};
+use url::Url;
 use zip::CompressionMethod;
 use zip::write::{SimpleFileOptions, ZipWriter};
 use zstd::stream::write::Decoder;
 
-use crate::{Group, ModInfo, NeededFiles};
+use crate::{game_path, Group, GroupManipulations, GroupOptionGameArchive, ModInfo, NeededFiles, SelectionType, TtmpOptions, TtmpTarget};
+use crate::deduplicate::temp_file_for;
+use crate::error::Error;
+use crate::meta::{self, Manipulation};
+use crate::multi_writer::MultiWriter;
+use crate::verify::{self, FileKey};
 
 #[derive(Serialize, Clone)]
 #[serde(rename_all = "camelCase", tag = "kind")]
 enum TtmpProgress {
     CreatingManifest,
+    CheckingSpace {
+        current: usize,
+        total: usize,
+    },
+    #[serde(rename_all = "camelCase")]
+    SpaceChecked {
+        /// The uncompressed size of every file, as far as it's known.
+        files_size: u64,
+        output_size: u64,
+        /// The estimates leave little room on one of the drives.
+        low_space: bool,
+        /// How many files have no size or only their compressed size, which
+        /// makes the estimates too low.
+        unknown_sizes: usize,
+    },
     DownloadingFiles {
         current: usize,
         total: usize,
     },
     ProcessingArchive,
+    Verifying,
     Done,
 }
 
 impl TtmpProgress {
     pub const EVENT: &'static str = "ttmp-progress";
 
-    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> tauri::Result<()> {
+    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> Result<(), Error> {
+        // this only fails if the window is gone, so there's no one to report to
         window.emit_all(Self::EVENT, self)
+            .map_err(|_| Error::Cancelled)
     }
 }
 
-lazy_static::lazy_static! {
-    static ref DAT_FILES: HashMap<&'static str, &'static str> = maplit::hashmap! {
-        "common" => "000000",
-        "bgcommon" => "010000",
-        "bg" => "020000",
-        "cut" => "030000",
-        "chara" => "040000",
-        "shader" => "050000",
-        "ui" => "060000",
-        "sound" => "070000",
-        "vfx" => "080000",
-        "ui_script" => "090000",
-        "exd" => "0a0000",
-        "game_script" => "0b0000",
-        "music" => "0c0000",
-        "sqpack_test" => "120000",
-        "debug" => "130000",
-    };
+/// The TexTools framework version that introduced metadata files.
+const METADATA_FRAMEWORK_VERSION: &str = "2.0.0.0";
+/// The TexTools framework version that introduced the v2 manifest.
+const V2_FRAMEWORK_VERSION: &str = "1.3.0.0";
+
+/// Zstandard frame magic number.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// The most a Zstandard frame header can take up, including the magic number.
+const ZSTD_FRAME_HEADER_MAX: usize = 18;
+
+/// How many files to look up the size of at once.
+const SIZE_REQUESTS: usize = 16;
+
+/// The size of a file that's going to be read.
+enum FileSize {
+    /// The size of the file once decompressed.
+    Exact(u64),
+    /// The size the file is stored at, which is less than it'll take up.
+    Compressed(u64),
+    /// The server didn't say.
+    Unknown,
 }
 
-pub async fn create_ttmp_inner<R: Runtime>(window: Window<R>, path: &str, info: ModInfo, groups: Vec<Group>, needed_files: NeededFiles) -> anyhow::Result<()> {
-    TtmpProgress::CreatingManifest.emit(&window)?;
-
-    // create a manifest
-    let mut pages: HashMap<String, ModPackPage> = HashMap::new();
-    let mut simple_mods = Vec::new();
-
-    let groups: HashMap<String, Group> = groups.into_iter()
-        .map(|group| (group.name.clone(), group))
-        .collect();
+/// Where the files listed in [`NeededFiles`] come from.
+#[derive(Clone)]
+enum FileSource {
+    /// Download each file from the base uri. Files are zstd-compressed.
+    Remote(reqwest::Client),
+    /// Read each file from a directory, named by its hash.
+    Local(PathBuf),
+}
 
-    for uses in needed_files.files.values() {
-        for use_ in uses {
-            let dat_file = use_.2.split('/')
-                .next()
-                .and_then(|first| DAT_FILES.get(first))
-                .copied()
-                .unwrap_or("040000")
-                .to_string();
-
-            let simple = SimpleMod {
-                name: "".into(),
-                full_path: use_.2.clone(),
-                mod_offset: 0, // this will be set by the encoder
-                mod_size: 0, // this will be set by the encoder
-                mod_pack_entry: None,
-                category: "Mod file".into(),
-                dat_file,
-                is_default: false,
-            };
+impl FileSource {
+    /// The number of bytes that will be read for a file. Servers don't have
+    /// to answer head requests, so a failed one only makes the size unknown.
+    async fn size(&self, base_uri: &Url, hash: &str) -> anyhow::Result<FileSize> {
+        match self {
+            Self::Remote(client) => {
+                let resp = client.head(file_url(base_uri, hash)?)
+                    .send()
+                    .await
+                    .and_then(|resp| resp.error_for_status());
+                let resp = match resp {
+                    Ok(resp) => resp,
+                    Err(_) => return Ok(FileSize::Unknown),
+                };
 
-            if use_.0.is_none() && use_.1.is_none() {
-                simple_mods.push(simple);
-                continue;
+                // the body of a head response is empty, so read the header
+                let size = resp.headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|len| len.to_str().ok())
+                    .and_then(|len| len.parse().ok());
+                Ok(size.map_or(FileSize::Unknown, FileSize::Compressed))
             }
+            Self::Local(dir) => {
+                let path = dir.join(hash);
+                let file = StdFile::open(&path)
+                    .with_context(|| format!("could not open {}", path.display()))?;
+                let len = file.metadata()?.len();
+
+                // mirrors may hold either the zstd files from the cdn or the
+                // raw files, and zstd frames usually say how big they'll be
+                let mut header = Vec::with_capacity(ZSTD_FRAME_HEADER_MAX);
+                file.take(ZSTD_FRAME_HEADER_MAX as u64).read_to_end(&mut header)?;
+                if !header.starts_with(&ZSTD_MAGIC) {
+                    return Ok(FileSize::Exact(len));
+                }
 
-            let group = use_.0.as_ref().unwrap();
-            let option = use_.1.as_ref().unwrap();
-
-            let num_pages = pages.len();
-            let page = pages.entry(group.clone())
-                .or_insert_with(|| {
-                    let g = groups.get(group).unwrap();
-                    ModPackPage {
-                        mod_groups: vec![
-                            ModGroup {
-                                group_name: group.clone(),
-                                option_list: g.options.iter()
-                                    .map(|opt| ModOption {
-                                        name: opt.name.clone(),
-                                        selection_type: g.selection_type.into(),
-                                        group_name: g.name.clone(),
-                                        description: opt.description.clone(),
-                                        image_path: opt.image_path.clone(),
-                                        is_checked: false,
-                                        mods_jsons: Vec::new(),
-                                    })
-                                    .collect(),
-                                selection_type: g.selection_type.into(),
-                            }
-                        ],
-                        page_index: num_pages as i32,
-                    }
-                });
-
-            let page_group = &mut page.mod_groups[0];
-            if let Some(opt) = page_group.option_list.iter_mut().find(|opt| opt.name == *option) {
-                opt.mods_jsons.push(simple);
+                Ok(match zstd::zstd_safe::get_frame_content_size(&header) {
+                    Ok(Some(size)) => FileSize::Exact(size),
+                    _ => FileSize::Compressed(len),
+                })
             }
         }
     }
+}
 
-    let mod_pack = ModPack {
-        ttmp_version: "1.3w".into(),
-        minimum_framework_version: Some("1.3.0.0".into()),
-        name: info.name,
-        author: info.author,
-        version: info.version,
-        description: Some(info.description),
-        url: Some(info.url),
-        mod_pack_pages: Some(pages.into_values().collect()),
-        simple_mods_list: Some(simple_mods),
-    };
+fn file_url(base_uri: &Url, hash: &str) -> anyhow::Result<Url> {
+    let mut url = base_uri.clone();
+    url.path_segments_mut()
+        .ok()
+        .context("url cannot be a base")?
+        .pop_if_empty()
+        .push(hash);
 
-    let client = reqwest::ClientBuilder::new()
-        .build()?;
+    Ok(url)
+}
+
+pub async fn create_ttmp_inner<R: Runtime>(window: Window<R>, path: &str, info: ModInfo, groups: Vec<Group>, needed_files: NeededFiles, options: TtmpOptions) -> anyhow::Result<()> {
+    TtmpProgress::CreatingManifest.emit(&window)?;
+
+    let PreparedManifest { manifest, meta_files, expected } = prepare_manifest(
+        info,
+        &groups,
+        &needed_files.files,
+        &needed_files.manipulations,
+        &options,
+    )?;
+
+    let source = if needed_files.base_uri.scheme() == "file" {
+        let dir = needed_files.base_uri.to_file_path()
+            .ok()
+            .context("invalid file uri")?;
+        FileSource::Local(dir)
+    } else {
+        let client = reqwest::ClientBuilder::new()
+            .build()?;
+        FileSource::Remote(client)
+    };
 
     let num_files = needed_files.files.len();
 
+    // work out how much we're going to write before starting
+    TtmpProgress::CheckingSpace {
+        current: 0,
+        total: num_files,
+    }.emit(&window)?;
+
+    // use the sizes heliosphere sent where there are any, and ask the source
+    // about the rest a few at a time
+    let hashes: Vec<&String> = needed_files.files.keys().collect();
+    let mut files_size = 0;
+    let mut largest_file = 0;
+    let mut unknown_sizes = 0;
+    let mut checked = 0;
+    for chunk in hashes.chunks(SIZE_REQUESTS) {
+        let lookups: Vec<_> = chunk.iter()
+            .map(|hash| {
+                let known = needed_files.sizes.get(*hash).copied();
+                let source = source.clone();
+                let base_uri = needed_files.base_uri.clone();
+                let hash = hash.to_string();
+                tauri::async_runtime::spawn(async move {
+                    match known {
+                        Some(size) => Ok(FileSize::Exact(size)),
+                        None => source.size(&base_uri, &hash).await,
+                    }
+                })
+            })
+            .collect();
+
+        for lookup in lookups {
+            let size = match lookup.await?? {
+                FileSize::Exact(size) => size,
+                FileSize::Compressed(size) => {
+                    unknown_sizes += 1;
+                    size
+                }
+                FileSize::Unknown => {
+                    unknown_sizes += 1;
+                    0
+                }
+            };
+            files_size += size;
+            largest_file = largest_file.max(size);
+
+            checked += 1;
+            TtmpProgress::CheckingSpace {
+                current: checked,
+                total: num_files,
+            }.emit(&window)?;
+        }
+    }
+
+    // the mpd ends up at most as big as the files, and the staging file
+    // holds one file at a time
+    let output_size = files_size + meta_files.iter().map(|(_, data)| data.len() as u64).sum::<u64>();
+    let low_space = check_space(Path::new(path), output_size + largest_file, output_size)?;
+
+    TtmpProgress::SpaceChecked {
+        files_size,
+        output_size,
+        low_space,
+        unknown_sizes,
+    }.emit(&window)?;
+
     TtmpProgress::DownloadingFiles {
         current: 0,
         total: num_files,
     }.emit(&window)?;
 
     let mpd = tempfile::tempfile().context("could not create temp file")?;
-    let mut encoder = Some(MpdEncoder::new(mpd, ManifestKind::V2(mod_pack), None));
+    let mut encoder = Some(MpdEncoder::new(mpd, manifest, None));
     let mut staging = Some(TokioFile::from_std(tempfile::tempfile().context("could not create temp file")?));
     for (i, (hash, uses)) in needed_files.files.into_iter().enumerate() {
         // truncate temp file
         staging.as_mut().unwrap().rewind().await?;
         staging.as_ref().unwrap().set_len(0).await?;
 
-        let mut url = needed_files.base_uri.clone();
-        url.path_segments_mut()
-            .ok()
-            .context("url cannot be a base")?
-            .pop_if_empty()
-            .push(&hash);
+        let mut hasher = Blake3::new();
+        let mut std_staging = staging.take().unwrap().into_std().await;
+        let mut writer = MultiWriter::new(BufWriter::new(&mut std_staging), &mut hasher);
 
-        // download file and save to temp file
-        let mut resp = client.get(url).send().await?
-            .error_for_status()?;
+        match &source {
+            FileSource::Remote(client) => {
+                let url = file_url(&needed_files.base_uri, &hash)?;
 
-        let mut std_staging = staging.take().unwrap().into_std().await;
-        let mut decoder = Decoder::new(BufWriter::new(&mut std_staging))?;
+                // download file and save to temp file
+                let mut resp = client.get(url).send().await?
+                    .error_for_status()?;
+
+                let mut decoder = Decoder::new(&mut writer)?;
 
-        while let Some(chunk) = resp.chunk().await? {
-            decoder.write_all(chunk.as_ref())?;
+                while let Some(chunk) = resp.chunk().await? {
+                    decoder.write_all(chunk.as_ref())?;
+                }
+
+                decoder.flush()?;
+            }
+            FileSource::Local(dir) => {
+                let path = dir.join(&hash);
+                let file = StdFile::open(&path)
+                    .with_context(|| format!("could not open {}", path.display()))?;
+                let mut reader = BufReader::new(file);
+
+                // mirrors may hold either the zstd files from the cdn or the
+                // raw files
+                if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+                    zstd::stream::copy_decode(reader, &mut writer)?;
+                } else {
+                    std::io::copy(&mut reader, &mut writer)?;
+                }
+            }
         }
 
-        decoder.flush()?;
-        drop(decoder);
+        writer.flush()?;
+        drop(writer);
+
+        let actual = data_encoding::BASE64URL_NOPAD.encode(hasher.finalize().as_bytes());
+        if actual != hash {
+            return Err(Error::HashMismatch {
+                expected: hash,
+                actual,
+                game_paths: uses.into_iter().map(|use_| use_.2).collect(),
+            }.into());
+        }
 
         staging = Some(TokioFile::from_std(std_staging));
 
@@ -195,15 +317,7 @@ pub async fn create_ttmp_inner<R: Runtime>(window: Window<R>, path: &str, info:
                     game_path: use_.2,
                 };
 
-                let path = &info.game_path;
-
-                if path.ends_with(".tex") || path.ends_with(".atex") {
-                    std_encoder.add_texture_file(info, file_size as usize, &mut std_staging)?;
-                } else if path.ends_with(".mdl") {
-                    std_encoder.add_model_file(info, file_size as usize, &mut std_staging)?;
-                } else {
-                    std_encoder.add_standard_file(info, file_size as usize, &mut std_staging)?;
-                }
+                encode_file(&mut std_encoder, info, file_size as usize, &mut std_staging)?;
             }
 
             let tuple = (Some(std_encoder), Some(TokioFile::from_std(std_staging)));
@@ -221,33 +335,330 @@ pub async fn create_ttmp_inner<R: Runtime>(window: Window<R>, path: &str, info:
 
     TtmpProgress::ProcessingArchive.emit(&window)?;
 
-    let path = path.to_string();
+    let path = PathBuf::from(path);
+    let destination = path.clone();
+    let temp = tauri::async_runtime::spawn_blocking(move || {
+        write_ttmp(encoder.unwrap(), meta_files, Vec::new(), &path)
+    }).await??;
+
+    TtmpProgress::Verifying.emit(&window)?;
+
+    // only replace the destination once the new archive is known to be good
     tauri::async_runtime::spawn_blocking(move || {
-        let (manifest, mut file) = encoder.unwrap().finalize()?;
-        file.seek(SeekFrom::Start(0))?;
-
-        let ttmp_file = StdFile::create(path)?;
-        let mut zip = ZipWriter::new(ttmp_file);
-        zip.start_file("TTMPL.mpl", SimpleFileOptions::default().compression_method(CompressionMethod::Deflated))?;
-        match manifest {
-            ManifestKind::V2(packs) => serde_json::to_writer(&mut zip, &packs)?,
-            ManifestKind::V1(mods) => {
-                for mod_ in mods {
-                    serde_json::to_writer(&mut zip, &mod_)?;
-                    zip.write_all(b"\n")?;
-                }
+        verify::verify_ttmp(BufReader::new(temp.reopen()?), &expected)?;
+        temp.persist(&destination)?;
+        Result::<_, anyhow::Error>::Ok(())
+    }).await??;
+
+    TtmpProgress::Done.emit(&window)?;
+
+    Ok(())
+}
+
+/// A manifest waiting for its files to be encoded.
+pub struct PreparedManifest {
+    pub manifest: ManifestKind,
+    /// TexTools metadata files generated from the manipulations, which still
+    /// need to be added to the encoder.
+    pub meta_files: Vec<(FileInfo, Vec<u8>)>,
+    /// The hash of every file the finished archive should contain.
+    pub expected: HashMap<FileKey, String>,
+}
+
+/// Build the manifest for a modpack, with every file use in `files` (keyed by
+/// hash) placed in its group and option, and generate the metadata files
+/// for the manipulations.
+pub fn prepare_manifest(info: ModInfo, groups: &[Group], files: &HashMap<String, Vec<GroupOptionGameArchive>>, manipulations: &[GroupManipulations], options: &TtmpOptions) -> anyhow::Result<PreparedManifest> {
+    // generate the TexTools metadata files for any manipulations
+    let mut meta_files = Vec::new();
+    for group in manipulations {
+        for option in &group.options {
+            let manipulations = option.manipulations.iter()
+                .cloned()
+                .map(serde_json::from_value)
+                .collect::<Result<Vec<Manipulation>, _>>()
+                .map_err(|e| Error::InvalidInput(format!("invalid manipulation: {e}")))?;
+
+            for (game_path, data) in meta::textools_files(&manipulations)? {
+                let info = FileInfo {
+                    group: group.name.clone(),
+                    option: option.name.clone(),
+                    game_path,
+                };
+
+                meta_files.push((info, data));
             }
         }
+    }
 
-        zip.start_file("TTMPD.mpd", SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
-        std::io::copy(&mut file, &mut zip)?;
+    // make sure the default settings make sense before using them
+    for g in groups {
+        if matches!(g.selection_type, SelectionType::Single) && !g.options.is_empty() && g.default_settings as usize >= g.options.len() {
+            return Err(Error::InvalidInput(format!(
+                "single-choice group {} must have exactly one default option, but its default is option {} of {}",
+                g.name,
+                g.default_settings + 1,
+                g.options.len(),
+            )).into());
+        }
+    }
+
+    // create a manifest
+    let mut mod_groups: Vec<ModGroup> = groups.iter()
+        .map(|g| ModGroup {
+            group_name: g.name.clone(),
+            option_list: g.options.iter()
+                .enumerate()
+                .map(|(i, opt)| ModOption {
+                    name: opt.name.clone(),
+                    selection_type: g.selection_type.into(),
+                    group_name: g.name.clone(),
+                    description: opt.description.as_deref().map(|desc| options.description_format.convert(desc)),
+                    image_path: opt.image_path.clone(),
+                    is_checked: g.is_default(i),
+                    mods_jsons: Vec::new(),
+                })
+                .collect(),
+            selection_type: g.selection_type.into(),
+        })
+        .collect();
+    let mut simple_mods = Vec::new();
 
-        zip.finish()?;
+    let meta_hashes = meta_files.iter()
+        .map(|(_, data)| verify::content_hash(data.as_slice()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let uses = files.iter()
+        .flat_map(|(hash, uses)| uses.iter().map(move |use_| (hash, &use_.0, &use_.1, &use_.2)))
+        .chain(meta_files.iter().zip(&meta_hashes).map(|((info, _), hash)| (hash, &info.group, &info.option, &info.game_path)));
+
+    // what the finished archive should contain, to check it against
+    let mut expected = HashMap::new();
+    let mut invalid_paths = BTreeSet::new();
+    for (hash, group, option, game_path) in uses {
+        let dat_file = match game_path::dat_file(game_path) {
+            Ok(dat_file) => dat_file,
+            Err(reason) => {
+                invalid_paths.insert(format!("{game_path} ({reason})"));
+                continue;
+            }
+        };
+
+        let mut simple = SimpleMod {
+            name: "".into(),
+            full_path: game_path.clone(),
+            mod_offset: 0, // this will be set by the encoder
+            mod_size: 0, // this will be set by the encoder
+            mod_pack_entry: None,
+            category: "Mod file".into(),
+            dat_file,
+            is_default: false,
+        };
+
+        let (group, option) = match (group, option) {
+            (Some(group), Some(option)) => (group, option),
+            _ => {
+                simple_mods.push(simple);
+                expected.insert((None, None, game_path.clone()), hash.clone());
+                continue;
+            }
+        };
 
-        Result::<(), anyhow::Error>::Ok(())
-    }).await??;
+        let mod_group = mod_groups.iter_mut()
+            .find(|g| g.group_name == *group)
+            .with_context(|| format!("unknown group {group}"))?;
 
-    TtmpProgress::Done.emit(&window)?;
+        if let Some(opt) = mod_group.option_list.iter_mut().find(|opt| opt.name == *option) {
+            simple.is_default = opt.is_checked;
+            opt.mods_jsons.push(simple);
+            expected.insert((Some(group.clone()), Some(option.clone()), game_path.clone()), hash.clone());
+        }
+    }
+
+    if !invalid_paths.is_empty() {
+        return Err(Error::InvalidGamePaths(invalid_paths.into_iter().collect()).into());
+    }
+
+    let pages = paginate(groups, mod_groups, options.groups_per_page.unwrap_or(1))?;
+
+    let manifest = match options.target {
+        TtmpTarget::Wizard => ManifestKind::V2(ModPack {
+            ttmp_version: "1.3w".into(),
+            minimum_framework_version: Some(minimum_framework_version(!meta_files.is_empty()).into()),
+            name: info.name,
+            author: info.author,
+            version: info.version,
+            description: Some(options.description_format.convert(&info.description)),
+            url: Some(info.url),
+            mod_pack_pages: Some(pages),
+            simple_mods_list: Some(simple_mods),
+        }),
+        TtmpTarget::SimpleV2 | TtmpTarget::SimpleV1 if !pages.is_empty() => {
+            return Err(Error::UnsupportedFormat("simple modpacks cannot contain options, use a wizard modpack instead".into()).into());
+        }
+        TtmpTarget::SimpleV2 => ManifestKind::V2(ModPack {
+            ttmp_version: "1.3s".into(),
+            minimum_framework_version: Some(minimum_framework_version(!meta_files.is_empty()).into()),
+            name: info.name,
+            author: info.author,
+            version: info.version,
+            description: Some(options.description_format.convert(&info.description)),
+            url: Some(info.url),
+            mod_pack_pages: None,
+            simple_mods_list: Some(simple_mods),
+        }),
+        TtmpTarget::SimpleV1 if !meta_files.is_empty() => {
+            return Err(Error::UnsupportedFormat("v1 modpacks cannot contain metadata files".into()).into());
+        }
+        TtmpTarget::SimpleV1 => ManifestKind::V1(simple_mods),
+    };
+
+    Ok(PreparedManifest {
+        manifest,
+        meta_files,
+        expected,
+    })
+}
+
+/// Add the metadata files, finish the data file and write out the modpack,
+/// along with any `extra_entries` like option images. The modpack is written
+/// to a temporary file next to `destination`, which the caller persists once
+/// it's been checked.
+pub fn write_ttmp(mut encoder: MpdEncoder<StdFile>, meta_files: Vec<(FileInfo, Vec<u8>)>, extra_entries: Vec<(String, Vec<u8>)>, destination: &Path) -> anyhow::Result<NamedTempFile> {
+    for (info, data) in meta_files {
+        let size = data.len();
+        encoder.add_standard_file(info, size, Cursor::new(data))?;
+    }
+
+    let (manifest, mut file) = encoder.finalize()?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let temp = temp_file_for(destination)?;
+    let mut zip = ZipWriter::new(temp);
+    zip.start_file("TTMPL.mpl", SimpleFileOptions::default().compression_method(CompressionMethod::Deflated))?;
+    match manifest {
+        ManifestKind::V2(packs) => serde_json::to_writer(&mut zip, &packs)?,
+        ManifestKind::V1(mods) => {
+            for mod_ in mods {
+                serde_json::to_writer(&mut zip, &mod_)?;
+                zip.write_all(b"\n")?;
+            }
+        }
+    }
+
+    zip.start_file("TTMPD.mpd", SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+    std::io::copy(&mut file, &mut zip)?;
+
+    for (name, data) in extra_entries {
+        zip.start_file(name, SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+        zip.write_all(&data)?;
+    }
+
+    Ok(zip.finish()?)
+}
+
+/// Add a file to the data file, compressed the way its type needs.
+pub fn encode_file<R: Read + Seek>(encoder: &mut MpdEncoder<StdFile>, info: FileInfo, size: usize, reader: R) -> anyhow::Result<()> {
+    let path = &info.game_path;
+    if path.ends_with(".tex") || path.ends_with(".atex") {
+        encoder.add_texture_file(info, size, reader)?;
+    } else if path.ends_with(".mdl") {
+        encoder.add_model_file(info, size, reader)?;
+    } else {
+        encoder.add_standard_file(info, size, reader)?;
+    }
 
     Ok(())
 }
+
+/// Lay the groups out on wizard pages, either on the pages the groups were
+/// assigned or by filling each page with up to `per_page` groups. Groups
+/// without any files are left out, and page order follows group order.
+fn paginate(groups: &[Group], mod_groups: Vec<ModGroup>, per_page: usize) -> anyhow::Result<Vec<ModPackPage>> {
+    let assigned = groups.iter().filter(|g| g.page.is_some()).count();
+    if assigned != 0 && assigned != groups.len() {
+        return Err(Error::InvalidInput("either every group or no group must be assigned a page".into()).into());
+    }
+
+    let used = groups.iter()
+        .zip(mod_groups)
+        .filter(|(_, mod_group)| mod_group.option_list.iter().any(|opt| !opt.mods_jsons.is_empty()));
+
+    let mut pages: BTreeMap<usize, Vec<ModGroup>> = BTreeMap::new();
+    for (i, (group, mod_group)) in used.enumerate() {
+        let page = group.page.unwrap_or(i / per_page.max(1));
+        pages.entry(page).or_default().push(mod_group);
+    }
+
+    let pages = pages.into_values()
+        .enumerate()
+        .map(|(i, mod_groups)| ModPackPage {
+            mod_groups,
+            page_index: i as i32,
+        })
+        .collect();
+
+    Ok(pages)
+}
+
+pub fn minimum_framework_version(has_metadata: bool) -> &'static str {
+    if has_metadata {
+        METADATA_FRAMEWORK_VERSION
+    } else {
+        V2_FRAMEWORK_VERSION
+    }
+}
+
+/// Make sure there's room for the temporary files and the finished modpack.
+/// Returns whether space is tight, since the sizes are only estimates.
+fn check_space(destination: &Path, temp_size: u64, output_size: u64) -> anyhow::Result<bool> {
+    let temp_dir = std::env::temp_dir();
+    let destination = match destination.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let needed = if same_volume(&temp_dir, &destination) {
+        vec![(temp_dir, temp_size + output_size)]
+    } else {
+        vec![(temp_dir, temp_size), (destination, output_size)]
+    };
+
+    let mut low_space = false;
+    for (dir, size) in needed {
+        let available = fs2::available_space(&dir)
+            .with_context(|| format!("could not check free space in {}", dir.display()))?;
+        if size > available {
+            return Err(Error::InsufficientSpace {
+                path: dir.display().to_string(),
+                needed: size,
+                available,
+            }.into());
+        }
+
+        low_space |= size + size / 4 > available;
+    }
+
+    Ok(low_space)
+}
+
+#[cfg(unix)]
+fn same_volume(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_volume(a: &Path, b: &Path) -> bool {
+    let prefix = |path: &Path| path.canonicalize()
+        .ok()
+        .and_then(|path| path.components().next().map(|root| root.as_os_str().to_ascii_lowercase()));
+
+    match (prefix(a), prefix(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
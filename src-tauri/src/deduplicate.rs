@@ -1,15 +1,22 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{Seek, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use serde::Serialize;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tauri::{Manager, Runtime, Window};
+use tempfile::NamedTempFile;
 use ttmp::model::ManifestKind;
 use ttmp::mpd_encoder::{FileInfo, MpdEncoder};
 use ttmp::ttmp_extractor::TtmpExtractor;
-use zip::{CompressionMethod, ZipWriter};
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 use zip::write::SimpleFileOptions;
 
+use crate::error::{CommandError, Error, ErrorCode};
+use crate::{pmp, verify};
+
 #[derive(Serialize, Clone)]
 #[serde(rename_all = "camelCase", tag = "kind")]
 enum DeduplicateProgress {
@@ -19,27 +26,162 @@ enum DeduplicateProgress {
         total: usize,
     },
     CreatingArchive,
-    Done,
+    Verifying,
+    Done {
+        report: DeduplicationReport,
+    },
+    /// Moving on to the next modpack of a folder.
+    BatchFile {
+        current: usize,
+        total: usize,
+        path: String,
+    },
+    BatchDone {
+        report: BatchReport,
+    },
 }
 
 impl DeduplicateProgress {
     pub const EVENT: &'static str = "deduplicate-progress";
 
-    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> tauri::Result<()> {
+    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> Result<(), Error> {
+        // this only fails if the window is gone, so there's no one to report to
         window.emit_all(Self::EVENT, self)
+            .map_err(|_| Error::Cancelled)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum DeduplicateOutput {
+    /// Write `<name>.deduplicated.<ext>` next to the input.
+    Beside,
+    /// Write to the given path.
+    Path {
+        path: String,
+    },
+    /// Replace the input, optionally keeping the original as
+    /// `<name>.<ext>.bak`.
+    InPlace {
+        backup: bool,
+    },
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeduplicationReport {
+    pub input_size: u64,
+    pub output_size: u64,
+    pub total_files: usize,
+    pub unique_files: usize,
+    /// Contents shared by more than one file, most wasteful first.
+    pub duplicates: Vec<DuplicateCluster>,
+    /// Archive entries other than the manifest and data that were copied
+    /// over as-is.
+    pub extra_entries: Vec<String>,
+    /// Extra entries that nothing in the manifest points to.
+    pub unreferenced_entries: Vec<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateCluster {
+    /// The hash of the contents, the same way Heliosphere hashes files.
+    pub hash: String,
+    pub size: u64,
+    pub uses: Vec<FileUse>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FileUse {
+    pub group: Option<String>,
+    pub option: Option<String>,
+    pub game_path: String,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchReport {
+    pub files: Vec<BatchEntry>,
+    /// Totals over the modpacks that were deduplicated.
+    pub input_size: u64,
+    pub output_size: u64,
+    pub failed: usize,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchEntry {
+    pub path: String,
+    #[serde(flatten)]
+    pub result: BatchResult,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum BatchResult {
+    #[serde(rename_all = "camelCase")]
+    Deduplicated {
+        input_size: u64,
+        output_size: u64,
+    },
+    Failed {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+impl Default for DeduplicateOutput {
+    fn default() -> Self {
+        Self::Beside
     }
 }
 
-pub fn deduplicate_inner<R: Runtime>(window: Window<R>, path: &str, compression: u32, threads: usize) -> anyhow::Result<()> {
+pub fn deduplicate_inner<R: Runtime>(window: Window<R>, path: &str, compression: u32, threads: usize, output: DeduplicateOutput) -> anyhow::Result<DeduplicationReport> {
     DeduplicateProgress::SettingUp.emit(&window)?;
 
+    let path = Path::new(path);
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    let report = match extension.as_deref() {
+        Some("ttmp" | "ttmp2") => deduplicate_ttmp(&window, path, compression, threads, &output)?,
+        Some("pmp") => deduplicate_pmp(&window, path, &output)?,
+        Some(ext) => return Err(Error::UnsupportedFormat(ext.into()).into()),
+        None => return Err(Error::UnsupportedFormat("could not determine file type from extension".into()).into()),
+    };
+
+    DeduplicateProgress::Done {
+        report: report.clone(),
+    }.emit(&window)?;
+
+    Ok(report)
+}
+
+fn deduplicate_ttmp<R: Runtime>(window: &Window<R>, path: &Path, compression: u32, threads: usize, output: &DeduplicateOutput) -> anyhow::Result<DeduplicationReport> {
     let file = File::open(path)?;
-    let extractor = TtmpExtractor::new(file)?;
+    let input_size = file.metadata()?.len();
+    let extractor = TtmpExtractor::new(file)
+        .map_err(|e| Error::InvalidArchive(e.to_string()))?;
+
+    // option images are the only other entries a ttmp refers to
+    let referenced: HashSet<String> = match extractor.manifest() {
+        ManifestKind::V2(pack) => pack.mod_pack_pages.iter()
+            .flatten()
+            .flat_map(|page| &page.mod_groups)
+            .flat_map(|group| &group.option_list)
+            .filter_map(|option| option.image_path.as_deref())
+            .map(normalise_entry)
+            .collect(),
+        ManifestKind::V1(_) => HashSet::new(),
+    };
 
     let files = extractor.all_files_sorted();
     let files_len = files.len();
     let mut zip = extractor.zip().borrow_mut();
-    let mut data = zip.by_name("TTMPD.mpd")?;
+    let mut data = zip.by_name("TTMPD.mpd")
+        .map_err(|_| Error::MissingEntry("TTMPD.mpd".into()))?;
 
     let mpd = tempfile::tempfile()?;
     let threads = if threads == 0 {
@@ -58,10 +200,16 @@ pub fn deduplicate_inner<R: Runtime>(window: Window<R>, path: &str, compression:
     DeduplicateProgress::ProcessingFiles {
         current: 0,
         total: files_len,
-    }.emit(&window)?;
+    }.emit(window)?;
 
     let mut last_offset = None;
     let mut last_hash: Option<Vec<u8>> = None;
+    let mut last_content_hash = String::new();
+    // the hash of every file, to check the new archive against
+    let mut expected = HashMap::new();
+    // the size, content hash and every use of each unique file, by the
+    // encoder's hash
+    let mut contents: HashMap<Vec<u8>, (u64, String, Vec<FileUse>)> = HashMap::new();
 
     for (i, file) in files.into_iter().enumerate() {
         let info = FileInfo {
@@ -69,11 +217,22 @@ pub fn deduplicate_inner<R: Runtime>(window: Window<R>, path: &str, compression:
             option: file.option.map(ToOwned::to_owned),
             game_path: file.file.full_path.clone(),
         };
+        let use_ = FileUse {
+            group: info.group.clone(),
+            option: info.option.clone(),
+            game_path: info.game_path.clone(),
+        };
+        let key = (info.group.clone(), info.option.clone(), info.game_path.clone());
 
         // handle deduped ttmps
         if Some(file.file.mod_offset) == last_offset {
+            expected.insert(key, last_content_hash.clone());
+
             if let Some(hash) = &last_hash {
                 encoder.add_file_info(hash, info);
+                if let Some((_, _, uses)) = contents.get_mut(hash) {
+                    uses.push(use_);
+                }
             }
 
             continue;
@@ -87,6 +246,9 @@ pub fn deduplicate_inner<R: Runtime>(window: Window<R>, path: &str, compression:
         TtmpExtractor::extract_one_into(&file, &mut data, &mut staging)?;
         let size = staging.metadata()?.len() as usize;
         staging.rewind()?;
+        last_content_hash = verify::content_hash(&mut staging)?;
+        expected.insert(key, last_content_hash.clone());
+        staging.rewind()?;
 
         last_hash = if info.game_path.ends_with(".mdl") {
             encoder.add_model_file(info, size, &mut staging)?
@@ -96,39 +258,402 @@ pub fn deduplicate_inner<R: Runtime>(window: Window<R>, path: &str, compression:
             encoder.add_standard_file(info, size, &mut staging)?
         }.into();
 
+        if let Some(hash) = &last_hash {
+            contents.entry(hash.clone())
+                .or_insert_with(|| (size as u64, last_content_hash.clone(), Vec::new()))
+                .2
+                .push(use_);
+        }
+
         DeduplicateProgress::ProcessingFiles {
             current: i + 1,
             total: files_len,
-        }.emit(&window)?;
+        }.emit(window)?;
     }
 
     let (manifest, mut mpd) = encoder.finalize()?;
     mpd.rewind()?;
+    drop(data);
 
-    let path = Path::new(&path);
-    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("ttmp2");
-    let new_path = path.with_extension(format!("deduplicated.{}", extension));
+    let destination = destination(path, output);
 
-    DeduplicateProgress::CreatingArchive.emit(&window)?;
+    DeduplicateProgress::CreatingArchive.emit(window)?;
 
-    let new_file = File::create(new_path)?;
-    let mut zip = ZipWriter::new(new_file);
+    let temp = temp_file_for(&destination)?;
+    let mut new_zip = ZipWriter::new(temp);
 
-    zip.start_file("TTMPL.mpl", SimpleFileOptions::default().compression_method(CompressionMethod::Deflated))?;
+    new_zip.start_file("TTMPL.mpl", SimpleFileOptions::default().compression_method(CompressionMethod::Deflated))?;
     match manifest {
         ManifestKind::V1(mods) => for mod_ in mods {
-            serde_json::to_writer(&mut zip, &mod_)?;
-            zip.write_all(b"\n")?;
+            serde_json::to_writer(&mut new_zip, &mod_)?;
+            new_zip.write_all(b"\n")?;
+        }
+        ManifestKind::V2(pack) => serde_json::to_writer(&mut new_zip, &pack)?,
+    }
+
+    new_zip.start_file("TTMPD.mpd", SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+    std::io::copy(&mut mpd, &mut new_zip)?;
+
+    // carry over everything else, like option images, without recompressing
+    let mut extra_entries = Vec::new();
+    let mut unreferenced_entries = Vec::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i)?;
+        let name = entry.name().to_string();
+        if name == "TTMPL.mpl" || name == "TTMPD.mpd" {
+            continue;
+        }
+
+        if !entry.is_dir() && !referenced.contains(&normalise_entry(&name)) {
+            unreferenced_entries.push(name.clone());
+        }
+
+        new_zip.raw_copy_file(entry)?;
+        extra_entries.push(name);
+    }
+
+    // close the input so it can be replaced
+    drop(zip);
+    drop(extractor);
+
+    let temp = new_zip.finish()?;
+
+    // make sure what we wrote is right before replacing anything
+    DeduplicateProgress::Verifying.emit(window)?;
+    verify::verify_ttmp(temp.reopen()?, &expected)?;
+
+    let output_size = replace_output(path, output, temp, &destination)?;
+
+    let unique_files = contents.len();
+    let mut duplicates: Vec<_> = contents.into_iter()
+        .filter(|(_, (_, _, uses))| uses.len() > 1)
+        .map(|(_, (size, hash, uses))| DuplicateCluster {
+            hash,
+            size,
+            uses,
+        })
+        .collect();
+    duplicates.sort_unstable_by_key(|cluster| std::cmp::Reverse(cluster.size * (cluster.uses.len() as u64 - 1)));
+
+    let report = DeduplicationReport {
+        input_size,
+        output_size,
+        total_files: files_len,
+        unique_files,
+        duplicates,
+        extra_entries,
+        unreferenced_entries,
+    };
+
+    Ok(report)
+}
+
+fn normalise_entry(name: &str) -> String {
+    name.replace('\\', "/").to_lowercase()
+}
+
+fn deduplicate_pmp<R: Runtime>(window: &Window<R>, path: &Path, output: &DeduplicateOutput) -> anyhow::Result<DeduplicationReport> {
+    let file = File::open(path)?;
+    let input_size = file.metadata()?.len();
+    let mut zip = ZipArchive::new(file)?;
+    let mut jsons = pmp::read_jsons(&mut zip)?;
+    let entries = pmp::entry_names(&zip);
+
+    // find the entry behind every file, in the order the maps list them
+    let mut resolved = Vec::new();
+    let mut uses: BTreeMap<String, Vec<FileUse>> = BTreeMap::new();
+    for map in pmp::files_maps(&mut jsons) {
+        for (game_path, local) in map.files.iter() {
+            let local = local.as_str()
+                .with_context(|| format!("invalid pmp: {game_path} does not point at a file"))?;
+            let entry = entries.get(&pmp::normalise(local))
+                .ok_or_else(|| Error::MissingEntry(local.to_string()))?;
+
+            uses.entry(entry.clone()).or_default().push(FileUse {
+                group: map.group.clone(),
+                option: map.option.clone(),
+                game_path: game_path.clone(),
+            });
+            resolved.push(entry.clone());
+        }
+    }
+
+    let total = uses.len();
+    DeduplicateProgress::ProcessingFiles {
+        current: 0,
+        total,
+    }.emit(window)?;
+
+    // entries are visited in order, so the first entry of each content is
+    // the one that's kept
+    let mut hashes = HashMap::new();
+    let mut contents: HashMap<String, (u64, Vec<String>)> = HashMap::new();
+    for (i, entry) in uses.keys().enumerate() {
+        let mut file = zip.by_name(entry)?;
+        let size = file.size();
+        let hash = verify::content_hash(&mut file)?;
+
+        contents.entry(hash.clone())
+            .or_insert_with(|| (size, Vec::new()))
+            .1
+            .push(entry.clone());
+        hashes.insert(entry.clone(), hash);
+
+        DeduplicateProgress::ProcessingFiles {
+            current: i + 1,
+            total,
+        }.emit(window)?;
+    }
+
+    // point every file at the kept copy of its content
+    let mut expected = HashMap::new();
+    let mut resolved = resolved.into_iter();
+    for map in pmp::files_maps(&mut jsons) {
+        for (game_path, local) in map.files.iter_mut() {
+            let entry = resolved.next().context("pmp changed while deduplicating")?;
+            let hash = &hashes[&entry];
+            let kept = &contents[hash].1[0];
+            expected.insert((map.group.clone(), map.option.clone(), game_path.clone()), hash.clone());
+
+            if *kept != entry {
+                // keep the slashes the author used
+                let backslashes = local.as_str().map_or(false, |local| local.contains('\\'));
+                *local = Value::String(if backslashes {
+                    kept.replace('/', "\\")
+                } else {
+                    kept.clone()
+                });
+            }
+        }
+    }
+
+    let redundant: HashSet<&str> = contents.values()
+        .flat_map(|(_, entries)| entries.iter().skip(1))
+        .map(String::as_str)
+        .collect();
+    let referenced: HashSet<String> = pmp::images(&jsons)
+        .map(normalise_entry)
+        .collect();
+
+    let destination = destination(path, output);
+
+    DeduplicateProgress::CreatingArchive.emit(window)?;
+
+    let temp = temp_file_for(&destination)?;
+    let mut new_zip = ZipWriter::new(temp);
+
+    // carry over everything that's still used without recompressing
+    let mut extra_entries = Vec::new();
+    let mut unreferenced_entries = Vec::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i)?;
+        let name = entry.name().to_string();
+        if name == "default_mod.json" || pmp::is_group(&name) || redundant.contains(name.as_str()) {
+            continue;
+        }
+
+        if name != "meta.json" && !uses.contains_key(&name) {
+            if !entry.is_dir() && !referenced.contains(&normalise_entry(&name)) {
+                unreferenced_entries.push(name.clone());
+            }
+
+            extra_entries.push(name);
+        }
+
+        new_zip.raw_copy_file(entry)?;
+    }
+
+    pmp::write_jsons(&mut new_zip, &jsons)?;
+
+    // close the input so it can be replaced
+    drop(zip);
+
+    let temp = new_zip.finish()?;
+
+    // make sure what we wrote is right before replacing anything
+    DeduplicateProgress::Verifying.emit(window)?;
+    verify::verify_pmp(temp.reopen()?, &expected)?;
+
+    let output_size = replace_output(path, output, temp, &destination)?;
+
+    let unique_files = contents.len();
+    let mut duplicates: Vec<_> = contents.into_iter()
+        .filter(|(_, (_, entries))| entries.len() > 1)
+        .map(|(hash, (size, entries))| {
+            let wasted = size * (entries.len() as u64 - 1);
+            let uses = entries.iter()
+                .flat_map(|entry| uses[entry].iter().cloned())
+                .collect();
+            (wasted, DuplicateCluster {
+                hash,
+                size,
+                uses,
+            })
+        })
+        .collect();
+    duplicates.sort_unstable_by_key(|(wasted, _)| std::cmp::Reverse(*wasted));
+
+    let report = DeduplicationReport {
+        input_size,
+        output_size,
+        total_files: uses.values().map(Vec::len).sum(),
+        unique_files,
+        duplicates: duplicates.into_iter().map(|(_, cluster)| cluster).collect(),
+        extra_entries,
+        unreferenced_entries,
+    };
+
+    Ok(report)
+}
+
+fn destination(path: &Path, output: &DeduplicateOutput) -> PathBuf {
+    match output {
+        DeduplicateOutput::Beside => {
+            let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("ttmp2");
+            path.with_extension(format!("deduplicated.{}", extension))
         }
-        ManifestKind::V2(pack) => serde_json::to_writer(&mut zip, &pack)?,
+        DeduplicateOutput::Path { path } => PathBuf::from(path),
+        DeduplicateOutput::InPlace { .. } => path.to_path_buf(),
     }
+}
 
-    zip.start_file("TTMPD.mpd", SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
-    std::io::copy(&mut mpd, &mut zip)?;
+/// Create the file to write into next to the destination, so it can be moved
+/// into place atomically.
+pub fn temp_file_for(destination: &Path) -> std::io::Result<NamedTempFile> {
+    let dir = match destination.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
 
-    zip.finish()?;
+    NamedTempFile::new_in(dir)
+}
+
+/// Move the finished file into place, making a backup first if asked to.
+/// Returns the size of the new file.
+fn replace_output(path: &Path, output: &DeduplicateOutput, temp: NamedTempFile, destination: &Path) -> anyhow::Result<u64> {
+    if let DeduplicateOutput::InPlace { backup: true } = output {
+        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("ttmp2");
+        let backup = path.with_extension(format!("{}.bak", extension));
+        if backup.exists() {
+            std::fs::remove_file(&backup)?;
+        }
 
-    DeduplicateProgress::Done.emit(&window)?;
+        // a hard link is free, but isn't always possible
+        if std::fs::hard_link(path, &backup).is_err() {
+            std::fs::copy(path, &backup)?;
+        }
+    }
+
+    let output_size = temp.as_file().metadata()?.len();
+    temp.persist(destination)?;
+
+    Ok(output_size)
+}
+
+/// Deduplicate every modpack in a folder. With [`DeduplicateOutput::Path`],
+/// the path is a folder the results are written into, keeping the layout of
+/// the input folder. A modpack that fails doesn't stop the others.
+pub fn deduplicate_folder_inner<R: Runtime>(window: Window<R>, path: &str, recursive: bool, compression: u32, threads: usize, output: DeduplicateOutput) -> anyhow::Result<BatchReport> {
+    DeduplicateProgress::SettingUp.emit(&window)?;
+
+    let root = Path::new(path);
+    let mut paths = Vec::new();
+    find_modpacks(root, recursive, &mut paths)?;
+    paths.sort_unstable();
+
+    let total = paths.len();
+    let mut files = Vec::with_capacity(total);
+    let mut input_size = 0;
+    let mut output_size = 0;
+    let mut failed = 0;
+
+    for (i, file) in paths.into_iter().enumerate() {
+        let display = file.to_string_lossy().into_owned();
+        DeduplicateProgress::BatchFile {
+            current: i,
+            total,
+            path: display.clone(),
+        }.emit(&window)?;
+
+        let output = match &output {
+            DeduplicateOutput::Path { path } => {
+                let destination = Path::new(path).join(file.strip_prefix(root)?);
+                if let Some(parent) = destination.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                DeduplicateOutput::Path {
+                    path: destination.to_string_lossy().into_owned(),
+                }
+            }
+            output => output.clone(),
+        };
+
+        let result = match deduplicate_inner(window.clone(), &display, compression, threads, output) {
+            Ok(report) => {
+                input_size += report.input_size;
+                output_size += report.output_size;
+                BatchResult::Deduplicated {
+                    input_size: report.input_size,
+                    output_size: report.output_size,
+                }
+            }
+            Err(e) => {
+                if let Some(Error::Cancelled) = e.downcast_ref::<Error>() {
+                    return Err(e);
+                }
+
+                failed += 1;
+                let e = CommandError::from(e);
+                BatchResult::Failed {
+                    code: e.code,
+                    message: e.message,
+                }
+            }
+        };
+
+        files.push(BatchEntry {
+            path: display,
+            result,
+        });
+    }
+
+    let report = BatchReport {
+        files,
+        input_size,
+        output_size,
+        failed,
+    };
+
+    DeduplicateProgress::BatchDone {
+        report: report.clone(),
+    }.emit(&window)?;
+
+    Ok(report)
+}
+
+fn find_modpacks(dir: &Path, recursive: bool, paths: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                find_modpacks(&path, recursive, paths)?;
+            }
+
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        // skip the results of earlier runs
+        if name.contains(".deduplicated.") {
+            continue;
+        }
+
+        if name.ends_with(".ttmp") || name.ends_with(".ttmp2") || name.ends_with(".pmp") {
+            paths.push(path);
+        }
+    }
 
     Ok(())
 }
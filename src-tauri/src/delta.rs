@@ -15,6 +15,7 @@ use zip::{ZipArchive, ZipWriter};
 use zip::write::SimpleFileOptions;
 
 use crate::NeededFiles;
+use crate::error::Error;
 
 #[derive(Serialize, Clone)]
 #[serde(rename_all = "camelCase", tag = "kind")]
@@ -35,8 +36,10 @@ enum DeltaProgress {
 impl DeltaProgress {
     pub const EVENT: &'static str = "delta-progress";
 
-    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> tauri::Result<()> {
+    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> Result<(), Error> {
+        // this only fails if the window is gone, so there's no one to report to
         window.emit_all(Self::EVENT, self)
+            .map_err(|_| Error::Cancelled)
     }
 }
 
@@ -62,6 +65,8 @@ pub struct PenumbraGroup {
     pub priority: i32,
     #[serde(default)]
     pub default_settings: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
     #[serde(rename = "Type", flatten)]
     pub kind: GroupKind,
 }
@@ -135,8 +140,8 @@ pub fn delta_inner<R: Runtime>(window: Window<R>, path: &str, info: DeltaInfo) -
     let path = Path::new(path);
     match path.extension().and_then(|ext| ext.to_str()) {
         Some("pmp") => pmp_delta(window, path, info),
-        Some("ttmp" | "ttmp2") => anyhow::bail!("textools not supported"),
-        Some(_) | None => anyhow::bail!("could not determine file type from extension"),
+        Some(ext @ ("ttmp" | "ttmp2")) => Err(Error::UnsupportedFormat(ext.into()).into()),
+        Some(_) | None => Err(Error::UnsupportedFormat("could not determine file type from extension".into()).into()),
     }
 }
 
@@ -147,7 +152,7 @@ fn pmp_delta<R: Runtime>(window: Window<R>, path: &Path, info: DeltaInfo) -> any
     let files: Vec<_> = zip.file_names().map(ToOwned::to_owned).collect();
 
     if !files.iter().any(|file| file == "meta.json") {
-        anyhow::bail!("invalid pmp: missing meta.json");
+        return Err(Error::InvalidArchive("pmp is missing meta.json".into()).into());
     }
 
     // find all the groups and the default group, parse them
@@ -158,7 +163,7 @@ fn pmp_delta<R: Runtime>(window: Window<R>, path: &Path, info: DeltaInfo) -> any
     let mut groups = Vec::with_capacity(group_paths.len());
     let mut default: PenumbraStandardOptionSimple = {
         let file = zip.by_name("default_mod.json")
-            .context("could not get default_mod.json")?;
+            .map_err(|_| Error::MissingEntry("default_mod.json".into()))?;
         let without_bom = skip_bom(file)
             .context("could not skip bom")?;
         serde_json::from_reader(without_bom)
@@ -223,7 +228,9 @@ fn pmp_delta<R: Runtime>(window: Window<R>, path: &Path, info: DeltaInfo) -> any
     let mut path_hashes = HashMap::new();
     let mut hash_to_path = HashMap::new();
     for (i, path) in to_hash.iter().enumerate() {
-        std::io::copy(&mut zip.by_name(path)?, &mut hasher)?;
+        let mut file = zip.by_name(path)
+            .map_err(|_| Error::MissingEntry(path.clone()))?;
+        std::io::copy(&mut file, &mut hasher)?;
         let hash = data_encoding::BASE64URL_NOPAD.encode(&hasher.finalize_reset());
         path_hashes.insert(path, hash.clone());
         hash_to_path.insert(hash, path);
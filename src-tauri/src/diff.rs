@@ -0,0 +1,841 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use ttmp::model::{ManifestKind, SelectionType as TtmpSelectionType};
+use ttmp::ttmp_extractor::TtmpExtractor;
+use zip::ZipArchive;
+
+use crate::{NeededFiles, meta, pmp, verify};
+use crate::delta::{GroupKind, PenumbraGroup, PenumbraStandardOptionSimple};
+use crate::error::Error;
+use crate::meta::Manipulation;
+use crate::pmp::PenumbraMeta;
+
+/// Everything about a modpack that's worth comparing, the same for both
+/// formats.
+struct Snapshot {
+    metadata: BTreeMap<&'static str, String>,
+    default: OptionSnapshot,
+    groups: Vec<GroupSnapshot>,
+    /// The uncompressed size of each file by its hash.
+    sizes: HashMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Drop everything Heliosphere doesn't keep track of in its list of
+    /// needed files, so a local pack can be compared to a published version.
+    fn files_only(mut self) -> Self {
+        fn strip(option: &mut OptionSnapshot) {
+            option.file_swaps.clear();
+        }
+
+        self.metadata.clear();
+        strip(&mut self.default);
+        for group in &mut self.groups {
+            group.kind = "";
+            group.defaults.clear();
+            group.options.iter_mut().for_each(strip);
+            group.options.retain(|option| !option.files.is_empty() || !option.manipulations.is_empty());
+        }
+        self.groups.retain(|group| !group.options.is_empty());
+
+        self
+    }
+}
+
+struct GroupSnapshot {
+    name: String,
+    kind: &'static str,
+    defaults: BTreeSet<String>,
+    options: Vec<OptionSnapshot>,
+}
+
+#[derive(Default)]
+struct OptionSnapshot {
+    name: String,
+    /// Content hash by game path.
+    files: BTreeMap<String, String>,
+    /// Each swap as (from, to).
+    file_swaps: BTreeSet<(String, String)>,
+    /// Each manipulation as JSON, see [`manipulation_key`].
+    manipulations: BTreeSet<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModpackDiff {
+    pub metadata: Vec<FieldChange>,
+    pub groups_added: Vec<String>,
+    pub groups_removed: Vec<String>,
+    pub groups: Vec<GroupDiff>,
+    /// Changes to the files that aren't part of any group.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<OptionDiff>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupDiff {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<FieldChange>,
+    pub defaults_added: Vec<String>,
+    pub defaults_removed: Vec<String>,
+    pub options_added: Vec<String>,
+    pub options_removed: Vec<String>,
+    pub options: Vec<OptionDiff>,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionDiff {
+    pub name: String,
+    pub files_added: Vec<String>,
+    pub files_removed: Vec<String>,
+    /// Game paths that point at different contents.
+    pub files_changed: Vec<String>,
+    pub file_swaps_added: Vec<(String, String)>,
+    pub file_swaps_removed: Vec<(String, String)>,
+    pub manipulations_added: Vec<Value>,
+    pub manipulations_removed: Vec<Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffOutput {
+    pub diff: ModpackDiff,
+    pub markdown: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionChangelog {
+    pub diff: ModpackDiff,
+    /// Ready to paste into the version notes.
+    pub markdown: String,
+    /// How many files the published version doesn't already have.
+    pub new_files: usize,
+    pub new_size: u64,
+}
+
+/// Compare two modpacks, in either format, and describe what changed from
+/// `old` to `new`.
+pub fn diff_modpacks_inner(old: &str, new: &str) -> anyhow::Result<DiffOutput> {
+    let old = snapshot(Path::new(old)).context("could not read the old modpack")?;
+    let new = snapshot(Path::new(new)).context("could not read the new modpack")?;
+    let diff = diff(&old, &new)?;
+    let markdown = to_markdown(&diff);
+
+    Ok(DiffOutput {
+        diff,
+        markdown,
+    })
+}
+
+/// Describe what a local pack changes compared to the version already on
+/// Heliosphere, going by that version's needed files.
+pub fn version_changelog_inner(path: &str, needed_files: NeededFiles) -> anyhow::Result<VersionChangelog> {
+    let old = needed_files_snapshot(&needed_files);
+    let new = snapshot(Path::new(path))?.files_only();
+
+    // anything the published version doesn't have has to be uploaded
+    let mut new_hashes = BTreeSet::new();
+    for option in std::iter::once(&new.default).chain(new.groups.iter().flat_map(|group| &group.options)) {
+        new_hashes.extend(option.files.values().filter(|hash| !needed_files.files.contains_key(*hash)));
+    }
+    let new_size = new_hashes.iter()
+        .filter_map(|hash| new.sizes.get(*hash))
+        .sum();
+
+    let diff = diff(&old, &new)?;
+    let mut markdown = to_markdown(&diff);
+    if !new_hashes.is_empty() {
+        let _ = write!(markdown, "\n\n{} new files ({})", new_hashes.len(), human_size(new_size));
+    }
+
+    Ok(VersionChangelog {
+        diff,
+        markdown,
+        new_files: new_hashes.len(),
+        new_size,
+    })
+}
+
+fn needed_files_snapshot(needed_files: &NeededFiles) -> Snapshot {
+    let mut snapshot = Snapshot {
+        metadata: BTreeMap::new(),
+        default: OptionSnapshot::default(),
+        groups: Vec::new(),
+        sizes: HashMap::new(),
+    };
+
+    fn option<'a>(snapshot: &'a mut Snapshot, group: Option<&str>, option: Option<&str>) -> &'a mut OptionSnapshot {
+        let group = match group {
+            Some(group) => group,
+            None => return &mut snapshot.default,
+        };
+
+        let idx = match snapshot.groups.iter().position(|g| g.name == group) {
+            Some(idx) => idx,
+            None => {
+                snapshot.groups.push(GroupSnapshot {
+                    name: group.to_string(),
+                    kind: "",
+                    defaults: BTreeSet::new(),
+                    options: Vec::new(),
+                });
+                snapshot.groups.len() - 1
+            }
+        };
+
+        let options = &mut snapshot.groups[idx].options;
+        let name = option.unwrap_or_default();
+        let idx = match options.iter().position(|o| o.name == name) {
+            Some(idx) => idx,
+            None => {
+                options.push(OptionSnapshot {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+                options.len() - 1
+            }
+        };
+
+        &mut options[idx]
+    }
+
+    let mut files: Vec<_> = needed_files.files.iter()
+        .flat_map(|(hash, uses)| uses.iter().map(move |(group, opt, game_path, _)| (group, opt, game_path, hash)))
+        .collect();
+    files.sort_unstable();
+    for (group, opt, game_path, hash) in files {
+        option(&mut snapshot, group.as_deref(), opt.as_deref())
+            .files
+            .insert(game_path.clone(), hash.clone());
+    }
+
+    for group in &needed_files.manipulations {
+        for opt in &group.options {
+            option(&mut snapshot, group.name.as_deref(), opt.name.as_deref())
+                .manipulations
+                .extend(opt.manipulations.iter().map(manipulation_key));
+        }
+    }
+
+    snapshot
+}
+
+fn snapshot(path: &Path) -> anyhow::Result<Snapshot> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pmp") => pmp_snapshot(path),
+        Some("ttmp" | "ttmp2") => ttmp_snapshot(path),
+        Some(ext) => Err(Error::UnsupportedFormat(ext.into()).into()),
+        None => Err(Error::UnsupportedFormat("could not determine file type from extension".into()).into()),
+    }
+}
+
+fn pmp_snapshot(path: &Path) -> anyhow::Result<Snapshot> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let entries = pmp::entry_names(&zip);
+    let mut jsons = pmp::read_jsons(&mut zip)?;
+
+    let meta: PenumbraMeta = {
+        let file = zip.by_name("meta.json")?;
+        serde_json::from_value(pmp::read_json(file, "meta.json")?)
+            .context("invalid pmp: invalid meta.json")?
+    };
+
+    // hash every file once
+    let mut hashes: HashMap<String, String> = HashMap::new();
+    let mut sizes = HashMap::new();
+    for map in pmp::files_maps(&mut jsons) {
+        for local in map.files.values().filter_map(Value::as_str) {
+            let entry = entries.get(&pmp::normalise(local))
+                .ok_or_else(|| Error::MissingEntry(local.to_string()))?;
+            if !hashes.contains_key(entry) {
+                let file = zip.by_name(entry)?;
+                let size = file.size();
+                let hash = verify::content_hash(file)?;
+                sizes.insert(hash.clone(), size);
+                hashes.insert(entry.clone(), hash);
+            }
+        }
+    }
+
+    let option = |name: &str, simple: PenumbraStandardOptionSimple| OptionSnapshot {
+        name: name.to_string(),
+        files: simple.files.into_iter()
+            .map(|(game_path, local)| {
+                let hash = entries.get(&pmp::normalise(&local))
+                    .and_then(|entry| hashes.get(entry))
+                    .cloned()
+                    .unwrap_or_default();
+                (game_path, hash)
+            })
+            .collect(),
+        file_swaps: simple.file_swaps.into_iter().collect(),
+        manipulations: simple.manipulations.iter().map(manipulation_key).collect(),
+    };
+
+    let default = option("", serde_json::from_value(jsons.default)
+        .context("invalid pmp: invalid default_mod.json")?);
+
+    let mut groups = Vec::with_capacity(jsons.groups.len());
+    for (entry, group) in jsons.groups {
+        let group: PenumbraGroup = serde_json::from_value(group)
+            .with_context(|| format!("invalid pmp: invalid group {entry}"))?;
+        let (kind, options, names): (_, Vec<_>, Vec<_>) = match group.kind {
+            GroupKind::Single { options } => {
+                let names = options.iter().map(|option| option.name.clone()).collect();
+                ("single", options.into_iter().map(|opt| option(&opt.name, opt.simple)).collect(), names)
+            }
+            GroupKind::Multi { options } => {
+                let names = options.iter().map(|option| option.name.clone()).collect();
+                ("multi", options.into_iter().map(|opt| option(&opt.name, opt.simple)).collect(), names)
+            }
+            GroupKind::Imc { options, .. } => {
+                let names = options.iter().map(|option| option.name.clone()).collect();
+                let options = options.into_iter()
+                    .map(|opt| OptionSnapshot {
+                        name: opt.name,
+                        ..Default::default()
+                    })
+                    .collect();
+                ("imc", options, names)
+            }
+        };
+
+        let defaults = names.into_iter()
+            .enumerate()
+            .filter(|(i, _)| match kind {
+                "single" => *i == group.default_settings as usize,
+                _ => *i < 32 && group.default_settings & (1 << i) != 0,
+            })
+            .map(|(_, name)| name)
+            .collect();
+
+        groups.push(GroupSnapshot {
+            name: group.name,
+            kind,
+            defaults,
+            options,
+        });
+    }
+
+    let metadata = BTreeMap::from([
+        ("name", meta.name),
+        ("author", meta.author),
+        ("version", meta.version),
+        ("description", meta.description),
+        ("website", meta.website),
+    ]);
+
+    Ok(Snapshot {
+        metadata,
+        default,
+        groups,
+        sizes,
+    })
+}
+
+fn ttmp_snapshot(path: &Path) -> anyhow::Result<Snapshot> {
+    let extractor = TtmpExtractor::new(File::open(path)?)
+        .map_err(|e| Error::InvalidArchive(e.to_string()))?;
+
+    // extract every file to hash it, and read the metadata files back into
+    // manipulations so they compare the same way a pmp's do
+    let mut options: HashMap<(Option<String>, Option<String>), OptionSnapshot> = HashMap::new();
+    let mut sizes = HashMap::new();
+    {
+        let files = extractor.all_files_sorted();
+        let mut zip = extractor.zip().borrow_mut();
+        let mut data = zip.by_name("TTMPD.mpd")
+            .map_err(|_| Error::MissingEntry("TTMPD.mpd".into()))?;
+        let mut staging = tempfile::tempfile()?;
+
+        let mut last_offset = None;
+        let mut last_hash = String::new();
+        let mut last_manipulations = Vec::new();
+        for file in files {
+            let game_path = file.file.full_path.clone();
+            let is_meta = game_path.ends_with(".meta") || game_path.ends_with(".rgsp");
+
+            // deduplicated files share their data
+            if Some(file.file.mod_offset) != last_offset {
+                last_offset = Some(file.file.mod_offset);
+
+                staging.rewind()?;
+                staging.set_len(0)?;
+                TtmpExtractor::extract_one_into(&file, &mut data, &mut staging)?;
+                let size = staging.stream_position()?;
+                staging.rewind()?;
+                last_hash = verify::content_hash(&mut staging)?;
+                sizes.insert(last_hash.clone(), size);
+
+                last_manipulations = Vec::new();
+                if is_meta {
+                    staging.rewind()?;
+                    let mut bytes = Vec::new();
+                    staging.read_to_end(&mut bytes)?;
+                    for manipulation in meta::manipulations(&game_path, &bytes)? {
+                        last_manipulations.push(serde_json::to_value(manipulation)?.to_string());
+                    }
+                }
+            }
+
+            let option = options.entry((file.group.map(ToOwned::to_owned), file.option.map(ToOwned::to_owned)))
+                .or_insert_with(|| OptionSnapshot {
+                    name: file.option.unwrap_or_default().to_string(),
+                    ..Default::default()
+                });
+            if is_meta {
+                option.manipulations.extend(last_manipulations.iter().cloned());
+            } else {
+                option.files.insert(game_path, last_hash.clone());
+            }
+        }
+    }
+
+    let default = options.remove(&(None, None)).unwrap_or_default();
+    let (metadata, groups) = match extractor.manifest() {
+        ManifestKind::V1(mods) => {
+            let entry = mods.iter().find_map(|mod_| mod_.mod_pack_entry.as_ref());
+            let metadata = BTreeMap::from([
+                ("name", entry.map(|entry| entry.name.clone()).unwrap_or_default()),
+                ("author", entry.map(|entry| entry.author.clone()).unwrap_or_default()),
+                ("version", entry.map(|entry| entry.version.clone()).unwrap_or_default()),
+            ]);
+
+            (metadata, Vec::new())
+        }
+        ManifestKind::V2(pack) => {
+            let metadata = BTreeMap::from([
+                ("name", pack.name.clone()),
+                ("author", pack.author.clone()),
+                ("version", pack.version.clone()),
+                ("description", pack.description.clone().unwrap_or_default()),
+                ("website", pack.url.clone().unwrap_or_default()),
+            ]);
+
+            let mut pages: Vec<_> = pack.mod_pack_pages.iter().flatten().collect();
+            pages.sort_by_key(|page| page.page_index);
+
+            let groups = pages.into_iter()
+                .flat_map(|page| &page.mod_groups)
+                .map(|group| GroupSnapshot {
+                    name: group.group_name.clone(),
+                    kind: if matches!(group.selection_type, TtmpSelectionType::Multi) { "multi" } else { "single" },
+                    defaults: group.option_list.iter()
+                        .filter(|option| option.is_checked)
+                        .map(|option| option.name.clone())
+                        .collect(),
+                    options: group.option_list.iter()
+                        .map(|option| options.remove(&(Some(group.group_name.clone()), Some(option.name.clone())))
+                            .unwrap_or_else(|| OptionSnapshot {
+                                name: option.name.clone(),
+                                ..Default::default()
+                            }))
+                        .collect(),
+                })
+                .collect();
+
+            (metadata, groups)
+        }
+    };
+
+    Ok(Snapshot {
+        metadata,
+        default,
+        groups,
+        sizes,
+    })
+}
+
+/// A manipulation as JSON, written the same way whichever format it came
+/// from so that equal manipulations compare equal.
+fn manipulation_key(value: &Value) -> String {
+    serde_json::from_value::<Manipulation>(value.clone())
+        .ok()
+        .and_then(|manipulation| serde_json::to_value(manipulation).ok())
+        .unwrap_or_else(|| value.clone())
+        .to_string()
+}
+
+fn diff(old: &Snapshot, new: &Snapshot) -> anyhow::Result<ModpackDiff> {
+    let metadata = old.metadata.keys()
+        .chain(new.metadata.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|field| {
+            let old = old.metadata.get(field).cloned().unwrap_or_default();
+            let new = new.metadata.get(field).cloned().unwrap_or_default();
+            (old != new).then(|| FieldChange {
+                field: field.to_string(),
+                old,
+                new,
+            })
+        })
+        .collect();
+
+    let (groups_added, groups_removed) = added_removed(
+        old.groups.iter().map(|group| &group.name),
+        new.groups.iter().map(|group| &group.name),
+    );
+
+    let mut groups = Vec::new();
+    for new_group in &new.groups {
+        let old_group = match old.groups.iter().find(|group| group.name == new_group.name) {
+            Some(group) => group,
+            None => continue,
+        };
+
+        let (options_added, options_removed) = added_removed(
+            old_group.options.iter().map(|option| &option.name),
+            new_group.options.iter().map(|option| &option.name),
+        );
+        let (defaults_added, defaults_removed) = added_removed(&old_group.defaults, &new_group.defaults);
+
+        let mut options = Vec::new();
+        for new_option in &new_group.options {
+            if let Some(old_option) = old_group.options.iter().find(|option| option.name == new_option.name) {
+                options.extend(diff_option(old_option, new_option)?);
+            }
+        }
+
+        let kind = (old_group.kind != new_group.kind).then(|| FieldChange {
+            field: "kind".into(),
+            old: old_group.kind.into(),
+            new: new_group.kind.into(),
+        });
+
+        let changed = kind.is_some()
+            || !options_added.is_empty()
+            || !options_removed.is_empty()
+            || !defaults_added.is_empty()
+            || !defaults_removed.is_empty()
+            || !options.is_empty();
+        if changed {
+            groups.push(GroupDiff {
+                name: new_group.name.clone(),
+                kind,
+                defaults_added,
+                defaults_removed,
+                options_added,
+                options_removed,
+                options,
+            });
+        }
+    }
+
+    Ok(ModpackDiff {
+        metadata,
+        groups_added,
+        groups_removed,
+        groups,
+        default: diff_option(&old.default, &new.default)?,
+    })
+}
+
+/// Compare two options, returning nothing if they're the same.
+fn diff_option(old: &OptionSnapshot, new: &OptionSnapshot) -> anyhow::Result<Option<OptionDiff>> {
+    let (files_added, files_removed) = added_removed(old.files.keys(), new.files.keys());
+    let files_changed = new.files.iter()
+        .filter(|(game_path, hash)| old.files.get(*game_path).map_or(false, |old| old != *hash))
+        .map(|(game_path, _)| game_path.clone())
+        .collect();
+
+    let (file_swaps_added, file_swaps_removed) = added_removed(&old.file_swaps, &new.file_swaps);
+    let (manipulations_added, manipulations_removed) = added_removed(&old.manipulations, &new.manipulations);
+
+    let parse = |manipulations: Vec<String>| manipulations.iter()
+        .map(|json| serde_json::from_str(json))
+        .collect::<Result<Vec<Value>, _>>();
+
+    let diff = OptionDiff {
+        name: new.name.clone(),
+        files_added,
+        files_removed,
+        files_changed,
+        file_swaps_added,
+        file_swaps_removed,
+        manipulations_added: parse(manipulations_added)?,
+        manipulations_removed: parse(manipulations_removed)?,
+    };
+
+    let unchanged = diff.files_added.is_empty()
+        && diff.files_removed.is_empty()
+        && diff.files_changed.is_empty()
+        && diff.file_swaps_added.is_empty()
+        && diff.file_swaps_removed.is_empty()
+        && diff.manipulations_added.is_empty()
+        && diff.manipulations_removed.is_empty();
+
+    Ok(if unchanged { None } else { Some(diff) })
+}
+
+/// Find what's only in `new` and what's only in `old`, keeping their order.
+fn added_removed<'a, T, O, N>(old: O, new: N) -> (Vec<T>, Vec<T>)
+where
+    T: Clone + Ord + 'a,
+    O: IntoIterator<Item = &'a T>,
+    N: IntoIterator<Item = &'a T>,
+{
+    let old: Vec<&T> = old.into_iter().collect();
+    let new: Vec<&T> = new.into_iter().collect();
+    let old_set: BTreeSet<&T> = old.iter().copied().collect();
+    let new_set: BTreeSet<&T> = new.iter().copied().collect();
+
+    let added = new.iter()
+        .filter(|item| !old_set.contains(*item))
+        .map(|item| (*item).clone())
+        .collect();
+    let removed = old.iter()
+        .filter(|item| !new_set.contains(*item))
+        .map(|item| (*item).clone())
+        .collect();
+
+    (added, removed)
+}
+
+/// Render a diff as a Markdown changelog.
+pub fn to_markdown(diff: &ModpackDiff) -> String {
+    let mut out = String::new();
+
+    if !diff.metadata.is_empty() {
+        out.push_str("## Details\n\n");
+        for change in &diff.metadata {
+            if change.field == "description" {
+                out.push_str("- Description changed\n");
+            } else {
+                let _ = writeln!(out, "- {}: `{}` → `{}`", title_case(&change.field), change.old, change.new);
+            }
+        }
+        out.push('\n');
+    }
+
+    if let Some(default) = &diff.default {
+        out.push_str("## Files outside of options\n\n");
+        write_option(&mut out, default, "");
+        out.push('\n');
+    }
+
+    if !diff.groups_added.is_empty() || !diff.groups_removed.is_empty() || !diff.groups.is_empty() {
+        out.push_str("## Options\n\n");
+        for group in &diff.groups_added {
+            let _ = writeln!(out, "- Added group **{group}**");
+        }
+        for group in &diff.groups_removed {
+            let _ = writeln!(out, "- Removed group **{group}**");
+        }
+
+        for group in &diff.groups {
+            let _ = writeln!(out, "- Changed group **{}**", group.name);
+            if let Some(kind) = &group.kind {
+                let _ = writeln!(out, "  - Changed from {} choice to {} choice", kind.old, kind.new);
+            }
+            for option in &group.options_added {
+                let _ = writeln!(out, "  - Added option **{option}**");
+            }
+            for option in &group.options_removed {
+                let _ = writeln!(out, "  - Removed option **{option}**");
+            }
+            for option in &group.defaults_added {
+                let _ = writeln!(out, "  - **{option}** is now enabled by default");
+            }
+            for option in &group.defaults_removed {
+                let _ = writeln!(out, "  - **{option}** is no longer enabled by default");
+            }
+            for option in &group.options {
+                let _ = writeln!(out, "  - Changed option **{}**", option.name);
+                write_option(&mut out, option, "    ");
+            }
+        }
+        out.push('\n');
+    }
+
+    if out.is_empty() {
+        out.push_str("No changes.\n");
+    }
+
+    out.trim_end().to_string()
+}
+
+fn write_option(out: &mut String, option: &OptionDiff, indent: &str) {
+    for path in &option.files_added {
+        let _ = writeln!(out, "{indent}- Added `{path}`");
+    }
+    for path in &option.files_removed {
+        let _ = writeln!(out, "{indent}- Removed `{path}`");
+    }
+    for path in &option.files_changed {
+        let _ = writeln!(out, "{indent}- Updated `{path}`");
+    }
+    for (from, to) in &option.file_swaps_added {
+        let _ = writeln!(out, "{indent}- Added swap `{from}` → `{to}`");
+    }
+    for (from, to) in &option.file_swaps_removed {
+        let _ = writeln!(out, "{indent}- Removed swap `{from}` → `{to}`");
+    }
+    if !option.manipulations_added.is_empty() {
+        let _ = writeln!(out, "{indent}- Added {} manipulations", option.manipulations_added.len());
+    }
+    if !option.manipulations_removed.is_empty() {
+        let _ = writeln!(out, "{indent}- Removed {} manipulations", option.manipulations_removed.len());
+    }
+}
+
+fn title_case(field: &str) -> String {
+    let mut chars = field.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.2} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(name: &str, files: &[(&str, &str)]) -> OptionSnapshot {
+        OptionSnapshot {
+            name: name.into(),
+            files: files.iter().map(|(path, hash)| (path.to_string(), hash.to_string())).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn snapshot(groups: Vec<GroupSnapshot>) -> Snapshot {
+        Snapshot {
+            metadata: BTreeMap::new(),
+            default: OptionSnapshot::default(),
+            groups,
+            sizes: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn added_removed_keeps_order() {
+        let old = vec!["a", "b", "c"];
+        let new = vec!["d", "c", "a", "e"];
+        assert_eq!(added_removed(&old, &new), (vec!["d", "e"], vec!["b"]));
+    }
+
+    #[test]
+    fn unchanged_option() {
+        let old = option("Option", &[("chara/a.tex", "x")]);
+        let new = option("Option", &[("chara/a.tex", "x")]);
+        assert!(diff_option(&old, &new).unwrap().is_none());
+    }
+
+    #[test]
+    fn changed_option() {
+        let old = option("Option", &[("chara/a.tex", "x"), ("chara/b.tex", "y")]);
+        let new = option("Option", &[("chara/a.tex", "z"), ("chara/c.tex", "y")]);
+
+        let diff = diff_option(&old, &new).unwrap().unwrap();
+        assert_eq!(diff.files_added, vec!["chara/c.tex"]);
+        assert_eq!(diff.files_removed, vec!["chara/b.tex"]);
+        assert_eq!(diff.files_changed, vec!["chara/a.tex"]);
+    }
+
+    #[test]
+    fn manipulation_keys_ignore_formatting() {
+        // the same manipulation, once with the defaults spelled out and the
+        // fields in a different order
+        let short: Value = serde_json::from_str(r#"{"Type":"Imc","Manipulation":{"Entry":{"MaterialId":1,"DecalId":0,"VfxId":0,"MaterialAnimationId":0,"AttributeMask":0,"SoundId":0},"ObjectType":"Equipment","PrimaryId":100,"Variant":1}}"#).unwrap();
+        let long: Value = serde_json::from_str(r#"{"Manipulation":{"Variant":1,"PrimaryId":100,"SecondaryId":0,"ObjectType":"Equipment","EquipSlot":"Unknown","BodySlot":"Unknown","Entry":{"SoundId":0,"AttributeMask":0,"MaterialAnimationId":0,"VfxId":0,"DecalId":0,"MaterialId":1}},"Type":"Imc"}"#).unwrap();
+        assert_eq!(manipulation_key(&short), manipulation_key(&long));
+
+        // anything that isn't a manipulation we know about is kept as is
+        let unknown: Value = serde_json::from_str(r#"{"Type":"Atch","Manipulation":{}}"#).unwrap();
+        assert_eq!(manipulation_key(&unknown), unknown.to_string());
+    }
+
+    #[test]
+    fn markdown_changelog() {
+        let old = snapshot(vec![
+            GroupSnapshot {
+                name: "Colour".into(),
+                kind: "single",
+                defaults: ["Red".to_string()].into_iter().collect(),
+                options: vec![
+                    option("Red", &[("chara/red.tex", "x")]),
+                    option("Blue", &[("chara/blue.tex", "y")]),
+                ],
+            },
+            GroupSnapshot {
+                name: "Old".into(),
+                kind: "multi",
+                defaults: BTreeSet::new(),
+                options: vec![option("Thing", &[])],
+            },
+        ]);
+        let new = snapshot(vec![
+            GroupSnapshot {
+                name: "Colour".into(),
+                kind: "single",
+                defaults: ["Red".to_string()].into_iter().collect(),
+                options: vec![
+                    option("Red", &[("chara/red.tex", "z")]),
+                    option("Green", &[("chara/green.tex", "y")]),
+                ],
+            },
+        ]);
+
+        let diff = diff(&old, &new).unwrap();
+        assert_eq!(to_markdown(&diff), "\
+## Options
+
+- Removed group **Old**
+- Changed group **Colour**
+  - Added option **Green**
+  - Removed option **Blue**
+  - Changed option **Red**
+    - Updated `chara/red.tex`");
+    }
+
+    #[test]
+    fn markdown_without_changes() {
+        let old = snapshot(vec![]);
+        let new = snapshot(vec![]);
+        assert_eq!(to_markdown(&diff(&old, &new).unwrap()), "No changes.");
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.50 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024), "3.00 GiB");
+    }
+}
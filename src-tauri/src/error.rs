@@ -0,0 +1,208 @@
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use zip::result::ZipError;
+
+/// Failures the frontend needs to be able to tell apart. These are raised
+/// inside the commands like any other error and picked back out of the
+/// [`anyhow::Error`] when it gets turned into a [`CommandError`].
+#[derive(Debug)]
+pub enum Error {
+    InvalidArchive(String),
+    MissingEntry(String),
+    MissingEntries(Vec<String>),
+    HashMismatch {
+        expected: String,
+        actual: String,
+        game_paths: Vec<String>,
+    },
+    InvalidGamePaths(Vec<String>),
+    /// Something the caller passed in can't be used.
+    InvalidInput(String),
+    UnsupportedFormat(String),
+    InsufficientSpace {
+        path: String,
+        needed: u64,
+        available: u64,
+    },
+    VerificationFailed(Vec<String>),
+    /// The page can only be seen with an account the session isn't logged
+    /// in to.
+    LoginRequired(String),
+    /// The window that started the operation went away.
+    Cancelled,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidArchive(reason) => write!(f, "invalid archive: {reason}"),
+            Self::MissingEntry(entry) => write!(f, "archive is missing {entry}"),
+            Self::MissingEntries(entries) => write!(f, "archive is missing:\n{}", entries.join("\n")),
+            Self::HashMismatch { expected, actual, .. } => write!(f, "hash mismatch: expected {expected}, got {actual}"),
+            Self::InvalidGamePaths(paths) => write!(f, "invalid game paths:\n{}", paths.join("\n")),
+            Self::InvalidInput(reason) => write!(f, "invalid input: {reason}"),
+            Self::UnsupportedFormat(format) => write!(f, "unsupported format: {format}"),
+            Self::InsufficientSpace { path, needed, available } => write!(
+                f,
+                "not enough free space in {path}: need about {needed} bytes, but only {available} bytes are available",
+            ),
+            Self::VerificationFailed(problems) => write!(f, "the written archive is not correct:\n{}", problems.join("\n")),
+            Self::LoginRequired(url) => write!(f, "{url} can only be viewed when logged in"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    InvalidArchive,
+    MissingEntry,
+    Network,
+    HttpStatus,
+    HashMismatch,
+    InvalidInput,
+    Io,
+    Cancelled,
+    UnsupportedFormat,
+    InsufficientSpace,
+    VerificationFailed,
+    LoginRequired,
+    Unknown,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorContext {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub game_paths: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub needed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+}
+
+/// The error returned to the frontend by every command.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub context: ErrorContext,
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(e: anyhow::Error) -> Self {
+        eprintln!("{e:#}\n{}", e.backtrace());
+
+        let mut context = ErrorContext::default();
+        let code = classify(&e, &mut context);
+
+        Self {
+            code,
+            message: format!("{e:#}"),
+            context,
+        }
+    }
+}
+
+fn classify(e: &anyhow::Error, context: &mut ErrorContext) -> ErrorCode {
+    // this also looks through any context attached to the error
+    if let Some(e) = e.downcast_ref::<Error>() {
+        return match e {
+            Error::InvalidArchive(_) => ErrorCode::InvalidArchive,
+            Error::MissingEntry(entry) => {
+                context.entry = Some(entry.clone());
+                ErrorCode::MissingEntry
+            }
+            Error::MissingEntries(entries) => {
+                context.entries = entries.clone();
+                ErrorCode::MissingEntry
+            }
+            Error::HashMismatch { expected, actual, game_paths } => {
+                context.hash = Some(expected.clone());
+                context.actual_hash = Some(actual.clone());
+                context.game_paths = game_paths.clone();
+                ErrorCode::HashMismatch
+            }
+            Error::InvalidGamePaths(paths) => {
+                context.game_paths = paths.clone();
+                ErrorCode::InvalidInput
+            }
+            Error::InvalidInput(_) => ErrorCode::InvalidInput,
+            Error::UnsupportedFormat(format) => {
+                context.format = Some(format.clone());
+                ErrorCode::UnsupportedFormat
+            }
+            Error::InsufficientSpace { path, needed, available } => {
+                context.path = Some(path.clone());
+                context.needed = Some(*needed);
+                context.available = Some(*available);
+                ErrorCode::InsufficientSpace
+            }
+            Error::VerificationFailed(problems) => {
+                context.problems = problems.clone();
+                ErrorCode::VerificationFailed
+            }
+            Error::LoginRequired(url) => {
+                context.url = Some(url.clone());
+                ErrorCode::LoginRequired
+            }
+            Error::Cancelled => ErrorCode::Cancelled,
+        };
+    }
+
+    for cause in e.chain() {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            context.url = e.url().map(ToString::to_string);
+            return match e.status() {
+                Some(status) => {
+                    context.status = Some(status.as_u16());
+                    ErrorCode::HttpStatus
+                }
+                None => ErrorCode::Network,
+            };
+        }
+
+        if let Some(e) = cause.downcast_ref::<ZipError>() {
+            return match e {
+                ZipError::Io(_) => ErrorCode::Io,
+                ZipError::FileNotFound => ErrorCode::MissingEntry,
+                _ => ErrorCode::InvalidArchive,
+            };
+        }
+
+        // bad json in the command's own input is raised as
+        // `Error::InvalidInput`, so anything left came from an archive
+        if cause.is::<serde_json::Error>() {
+            return ErrorCode::InvalidArchive;
+        }
+
+        if cause.is::<std::io::Error>() {
+            return ErrorCode::Io;
+        }
+    }
+
+    ErrorCode::Unknown
+}
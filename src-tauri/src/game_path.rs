@@ -0,0 +1,105 @@
+/// The sqpack categories, keyed by the root folder of the game paths they
+/// contain.
+const CATEGORIES: &[(&str, u8)] = &[
+    ("common", 0x00),
+    ("bgcommon", 0x01),
+    ("bg", 0x02),
+    ("cut", 0x03),
+    ("chara", 0x04),
+    ("shader", 0x05),
+    ("ui", 0x06),
+    ("sound", 0x07),
+    ("vfx", 0x08),
+    ("ui_script", 0x09),
+    ("exd", 0x0a),
+    ("game_script", 0x0b),
+    ("music", 0x0c),
+    ("sqpack_test", 0x12),
+    ("debug", 0x13),
+];
+
+/// Categories that are split into one dat file per expansion, using `exN`
+/// as the second folder.
+const EXPANSION_CATEGORIES: &[&str] = &["bg", "cut", "music"];
+
+const EXPANSIONS: u8 = 5;
+
+/// Work out the dat file a game path belongs in, e.g. `040000` for
+/// `chara/...` or `020300` for `bg/ex3/...`. Returns the reason the path is
+/// invalid if it can't be mapped.
+pub fn dat_file(game_path: &str) -> Result<String, String> {
+    if game_path.is_empty() {
+        return Err("path is empty".into());
+    }
+
+    if game_path.contains('\\') {
+        return Err("path uses backslashes instead of forward slashes".into());
+    }
+
+    let segments: Vec<&str> = game_path.split('/').collect();
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return Err("path has empty folder or file names".into());
+    }
+
+    if segments.len() < 2 {
+        return Err("path has no root folder".into());
+    }
+
+    let file_name = segments[segments.len() - 1];
+    if !file_name.contains('.') {
+        return Err("file name has no extension".into());
+    }
+
+    let root = segments[0];
+    let category = CATEGORIES.iter()
+        .find(|(name, _)| *name == root)
+        .map(|(_, category)| *category)
+        .ok_or_else(|| format!("unknown root folder {root}"))?;
+
+    let mut expansion = 0;
+    if EXPANSION_CATEGORIES.contains(&root) && segments.len() > 2 {
+        if let Some(ex) = segments[1].strip_prefix("ex") {
+            expansion = match ex.parse::<u8>() {
+                Ok(ex @ 1..=EXPANSIONS) => ex,
+                _ => return Err(format!("unknown expansion folder {}", segments[1])),
+            };
+        }
+    }
+
+    Ok(format!("{category:02x}{expansion:02x}00"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expansion_paths() {
+        assert_eq!(dat_file("bg/ex1/01_roc_r2/twn/r2t1/level/bg.lgb").unwrap(), "020100");
+        assert_eq!(dat_file("bg/ex5/01_ilm_i6/fld/i6f1/level/bg.lgb").unwrap(), "020500");
+        assert_eq!(dat_file("music/ex2/BGM_EX2_Field_Ala_01.scd").unwrap(), "0c0200");
+        assert_eq!(dat_file("cut/ex3/sequence/cut.cutb").unwrap(), "030300");
+    }
+
+    #[test]
+    fn base_game_paths() {
+        assert_eq!(dat_file("bg/ffxiv/sea_s1/twn/s1t1/level/bg.lgb").unwrap(), "020000");
+        assert_eq!(dat_file("music/ffxiv/BGM_System_Title.scd").unwrap(), "0c0000");
+        assert_eq!(dat_file("chara/equipment/e0100/model/c0101e0100_top.mdl").unwrap(), "040000");
+        // only some categories are split by expansion
+        assert_eq!(dat_file("chara/ex1/thing.tex").unwrap(), "040000");
+        // a file directly in the root folder isn't in an expansion folder
+        assert_eq!(dat_file("music/ex1.scd").unwrap(), "0c0000");
+    }
+
+    #[test]
+    fn invalid_paths() {
+        assert!(dat_file("bg/ex6/level/bg.lgb").is_err());
+        assert!(dat_file("bg/ex0/level/bg.lgb").is_err());
+        assert!(dat_file("music/exfoo/bgm.scd").is_err());
+        assert!(dat_file("chara\\equipment\\e0100.mdl").is_err());
+        assert!(dat_file("chara//e0100.mdl").is_err());
+        assert!(dat_file("unknown/file.tex").is_err());
+        assert!(dat_file("chara/equipment/e0100").is_err());
+    }
+}
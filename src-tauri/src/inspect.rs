@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+
+use serde::Serialize;
+use ttmp::model::{ManifestKind, ModOption, SelectionType as TtmpSelectionType, SimpleMod};
+use ttmp::ttmp_extractor::TtmpExtractor;
+
+use crate::error::Error;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ManifestVersion {
+    V1,
+    V2,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TtmpInspection {
+    pub kind: ManifestVersion,
+    pub ttmp_version: Option<String>,
+    pub minimum_framework_version: Option<String>,
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub pages: Vec<PageInfo>,
+    /// Files that aren't part of any option.
+    pub files: Vec<FileEntry>,
+    pub file_types: Vec<FileTypeInfo>,
+    pub archive_size: u64,
+    /// The size of the data file, counting data shared by several files once.
+    pub data_size: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageInfo {
+    pub index: i32,
+    pub groups: Vec<GroupInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupInfo {
+    pub name: String,
+    pub multi: bool,
+    pub options: Vec<OptionInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub image_path: Option<String>,
+    pub is_checked: bool,
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileEntry {
+    pub game_path: String,
+    pub dat_file: String,
+    pub category: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileTypeInfo {
+    pub extension: String,
+    pub count: usize,
+    pub size: u64,
+}
+
+/// Describe everything in a TexTools modpack without extracting it.
+pub fn inspect_ttmp_inner(path: &str) -> anyhow::Result<TtmpInspection> {
+    let file = File::open(path)?;
+    let archive_size = file.metadata()?.len();
+    let extractor = TtmpExtractor::new(file)
+        .map_err(|e| Error::InvalidArchive(e.to_string()))?;
+
+    let mut inspection = match extractor.manifest() {
+        ManifestKind::V1(mods) => {
+            let entry = mods.iter().find_map(|mod_| mod_.mod_pack_entry.as_ref());
+            TtmpInspection {
+                kind: ManifestVersion::V1,
+                ttmp_version: None,
+                minimum_framework_version: None,
+                name: entry.map(|entry| entry.name.clone()),
+                author: entry.map(|entry| entry.author.clone()),
+                version: entry.map(|entry| entry.version.clone()),
+                description: None,
+                url: None,
+                pages: Vec::new(),
+                files: mods.iter().map(file_entry).collect(),
+                file_types: Vec::new(),
+                archive_size,
+                data_size: 0,
+            }
+        }
+        ManifestKind::V2(pack) => {
+            let mut pages: Vec<_> = pack.mod_pack_pages.iter()
+                .flatten()
+                .map(|page| PageInfo {
+                    index: page.page_index,
+                    groups: page.mod_groups.iter()
+                        .map(|group| GroupInfo {
+                            name: group.group_name.clone(),
+                            multi: matches!(group.selection_type, TtmpSelectionType::Multi),
+                            options: group.option_list.iter().map(option_info).collect(),
+                        })
+                        .collect(),
+                })
+                .collect();
+            pages.sort_by_key(|page| page.index);
+
+            TtmpInspection {
+                kind: ManifestVersion::V2,
+                ttmp_version: Some(pack.ttmp_version.clone()),
+                minimum_framework_version: pack.minimum_framework_version.clone(),
+                name: Some(pack.name.clone()),
+                author: Some(pack.author.clone()),
+                version: Some(pack.version.clone()),
+                description: pack.description.clone(),
+                url: pack.url.clone(),
+                pages,
+                files: pack.simple_mods_list.iter().flatten().map(file_entry).collect(),
+                file_types: Vec::new(),
+                archive_size,
+                data_size: 0,
+            }
+        }
+    };
+
+    // break the files down by type, and work out how much data they take up
+    let mut types: BTreeMap<String, FileTypeInfo> = BTreeMap::new();
+    let mut offsets = HashSet::new();
+    for file in extractor.all_files_sorted() {
+        let game_path = &file.file.full_path;
+        let extension = game_path.rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase())
+            .unwrap_or_default();
+
+        let info = types.entry(extension.clone()).or_insert_with(|| FileTypeInfo {
+            extension,
+            count: 0,
+            size: 0,
+        });
+        info.count += 1;
+        info.size += file.file.mod_size as u64;
+
+        if offsets.insert(file.file.mod_offset) {
+            inspection.data_size += file.file.mod_size as u64;
+        }
+    }
+
+    inspection.file_types = types.into_values().collect();
+    inspection.file_types.sort_by_key(|info| std::cmp::Reverse(info.size));
+
+    Ok(inspection)
+}
+
+fn option_info(option: &ModOption) -> OptionInfo {
+    OptionInfo {
+        name: option.name.clone(),
+        description: option.description.clone(),
+        image_path: option.image_path.clone(),
+        is_checked: option.is_checked,
+        files: option.mods_jsons.iter().map(file_entry).collect(),
+    }
+}
+
+fn file_entry(file: &SimpleMod) -> FileEntry {
+    FileEntry {
+        game_path: file.full_path.clone(),
+        dat_file: file.dat_file.clone(),
+        category: file.category.clone(),
+        offset: file.mod_offset,
+        size: file.mod_size,
+    }
+}
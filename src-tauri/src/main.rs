@@ -9,7 +9,15 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use tauri::{Manager, Runtime, Window, WindowEvent};
 use url::Url;
+use crate::converters::xma::{ConvertedInfo, XmaSession};
+use crate::deduplicate::{BatchReport, DeduplicateOutput, DeduplicationReport};
 use crate::delta::DeltaInfo;
+use crate::diff::{DiffOutput, VersionChangelog};
+use crate::error::CommandError;
+use crate::inspect::TtmpInspection;
+use crate::pack::PackReport;
+use crate::upgrade::UpgradeInfo;
+use crate::validate::ValidationReport;
 
 use crate::state::State;
 
@@ -20,11 +28,22 @@ mod converters;
 mod create_ttmp;
 mod deduplicate;
 mod delta;
+mod diff;
+mod error;
+mod game_path;
+mod inspect;
+mod markdown;
+mod meta;
+mod pack;
+mod pmp;
+mod upgrade;
+mod validate;
+mod verify;
 
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_window_state::Builder::default().build())
-        .invoke_handler(tauri::generate_handler![create_ttmp, deduplicate, delta])
+        .invoke_handler(tauri::generate_handler![create_ttmp, deduplicate, deduplicate_folder, delta, diff_modpacks, import_from_xma, inspect_ttmp, pack_pmp, pmp_to_ttmp, set_xma_session, ttmp_to_pmp, upgrade_ttmp, validate_pmp, version_changelog])
         .setup(|app| {
             let resolver = app.path_resolver();
             let state = tauri::async_runtime::block_on(async {
@@ -49,21 +68,89 @@ fn main() {
 }
 
 #[tauri::command(async)]
-fn delta<R: Runtime>(window: Window<R>, path: &str, info: DeltaInfo) -> Result<(), String> {
+fn delta<R: Runtime>(window: Window<R>, path: &str, info: DeltaInfo) -> Result<(), CommandError> {
     delta::delta_inner(window, path, info)
-        .map_err(|e| format!("{e:#}\n{}", e.backtrace()))
+        .map_err(Into::into)
 }
 
 #[tauri::command]
-async fn create_ttmp<R: Runtime>(window: Window<R>, path: &str, info: ModInfo, groups: Vec<Group>, needed_files: NeededFiles) -> Result<(), String> {
-    create_ttmp::create_ttmp_inner(window, path, info, groups, needed_files).await
-        .map_err(|e| format!("{:#}\n{}", e, e.backtrace()))
+async fn create_ttmp<R: Runtime>(window: Window<R>, path: &str, info: ModInfo, groups: Vec<Group>, needed_files: NeededFiles, options: Option<TtmpOptions>) -> Result<(), CommandError> {
+    create_ttmp::create_ttmp_inner(window, path, info, groups, needed_files, options.unwrap_or_default()).await
+        .map_err(Into::into)
 }
 
 #[tauri::command(async)]
-fn deduplicate<R: Runtime>(window: Window<R>, path: &str, compression: u32, threads: usize) -> Result<(), String> {
-    deduplicate::deduplicate_inner(window, path, compression, threads)
-        .map_err(|e| format!("{:#}\n{}", e, e.backtrace()))
+fn deduplicate<R: Runtime>(window: Window<R>, path: &str, compression: u32, threads: usize, output: Option<DeduplicateOutput>) -> Result<DeduplicationReport, CommandError> {
+    deduplicate::deduplicate_inner(window, path, compression, threads, output.unwrap_or_default())
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn deduplicate_folder<R: Runtime>(window: Window<R>, path: &str, recursive: bool, compression: u32, threads: usize, output: Option<DeduplicateOutput>) -> Result<BatchReport, CommandError> {
+    deduplicate::deduplicate_folder_inner(window, path, recursive, compression, threads, output.unwrap_or_default())
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn ttmp_to_pmp<R: Runtime>(window: Window<R>, path: &str, output: Option<String>) -> Result<(), CommandError> {
+    converters::ttmp_to_pmp::ttmp_to_pmp_inner(window, path, output)
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn pmp_to_ttmp<R: Runtime>(window: Window<R>, path: &str, output: Option<String>, options: Option<TtmpOptions>) -> Result<(), CommandError> {
+    converters::pmp_to_ttmp::pmp_to_ttmp_inner(window, path, output, options.unwrap_or_default())
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn pack_pmp<R: Runtime>(window: Window<R>, path: &str, output: Option<String>, keep_unreferenced: bool) -> Result<PackReport, CommandError> {
+    pack::pack_pmp_inner(window, path, output, keep_unreferenced)
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn diff_modpacks(old: &str, new: &str) -> Result<DiffOutput, CommandError> {
+    diff::diff_modpacks_inner(old, new)
+        .map_err(Into::into)
+}
+
+#[tauri::command]
+async fn import_from_xma(state: tauri::State<'_, Arc<State>>, url: String) -> Result<ConvertedInfo, CommandError> {
+    converters::xma::xma_to_markdown(&state.xma, url).await
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn inspect_ttmp(path: &str) -> Result<TtmpInspection, CommandError> {
+    inspect::inspect_ttmp_inner(path)
+        .map_err(Into::into)
+}
+
+#[tauri::command]
+async fn set_xma_session(state: tauri::State<'_, Arc<State>>, cookie: Option<String>) -> Result<(), CommandError> {
+    let session = XmaSession::new(cookie.as_deref().filter(|cookie| !cookie.trim().is_empty()))?;
+    *state.xma.write().await = session;
+
+    Ok(())
+}
+
+#[tauri::command(async)]
+fn upgrade_ttmp(path: &str, output: Option<String>, info: Option<UpgradeInfo>) -> Result<(), CommandError> {
+    upgrade::upgrade_ttmp_inner(path, output, info.unwrap_or_default())
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn validate_pmp(path: &str) -> Result<ValidationReport, CommandError> {
+    validate::validate_pmp_inner(path)
+        .map_err(Into::into)
+}
+
+#[tauri::command(async)]
+fn version_changelog(path: &str, needed_files: NeededFiles) -> Result<VersionChangelog, CommandError> {
+    diff::version_changelog_inner(path, needed_files)
+        .map_err(Into::into)
 }
 
 #[derive(Deserialize, Serialize)]
@@ -80,6 +167,23 @@ type GroupOptionGameArchive = (Option<String>, Option<String>, String, Option<St
 pub struct NeededFiles {
     base_uri: Url,
     files: HashMap<String, Vec<GroupOptionGameArchive>>,
+    /// The uncompressed size of each file by hash, if the server sent them.
+    #[serde(default)]
+    sizes: HashMap<String, u64>,
+    #[serde(default)]
+    manipulations: Vec<GroupManipulations>,
+}
+
+#[derive(Deserialize)]
+pub struct GroupManipulations {
+    pub name: Option<String>,
+    pub options: Vec<OptionManipulations>,
+}
+
+#[derive(Deserialize)]
+pub struct OptionManipulations {
+    pub name: Option<String>,
+    pub manipulations: Vec<serde_json::Value>,
 }
 
 #[derive(Deserialize, Copy, Clone)]
@@ -113,6 +217,23 @@ pub struct Group {
     pub name: String,
     pub selection_type: SelectionType,
     pub options: Vec<OptionItem>,
+    /// The wizard page to put this group on. Groups sharing a page are shown
+    /// together.
+    #[serde(default)]
+    pub page: Option<usize>,
+    /// The options enabled by default: an index for single-choice groups and
+    /// a bitmask for multi-choice groups, same as Penumbra.
+    #[serde(default)]
+    pub default_settings: u32,
+}
+
+impl Group {
+    pub fn is_default(&self, option: usize) -> bool {
+        match self.selection_type {
+            SelectionType::Single => self.default_settings as usize == option,
+            SelectionType::Multi => option < 32 && self.default_settings & (1 << option) != 0,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -124,3 +245,56 @@ pub struct ModInfo {
     description: String,
     url: String,
 }
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TtmpOptions {
+    /// How many groups to put on each wizard page if the groups weren't
+    /// assigned pages. Defaults to one.
+    pub groups_per_page: Option<usize>,
+    pub target: TtmpTarget,
+    /// Descriptions are rendered as plain text, since TexTools can't
+    /// display Markdown, unless Markdown is asked for.
+    pub description_format: DescriptionFormat,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum TtmpTarget {
+    /// A v1 modpack with a line-delimited manifest and no options.
+    SimpleV1,
+    /// A v2 modpack without options.
+    SimpleV2,
+    /// A v2 modpack with a wizard.
+    Wizard,
+}
+
+impl Default for TtmpTarget {
+    fn default() -> Self {
+        Self::Wizard
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum DescriptionFormat {
+    /// Keep the Markdown from Heliosphere as-is.
+    Markdown,
+    /// Render the Markdown as plain text, since TexTools can't display it.
+    PlainText,
+}
+
+impl Default for DescriptionFormat {
+    fn default() -> Self {
+        Self::PlainText
+    }
+}
+
+impl DescriptionFormat {
+    pub fn convert(self, description: &str) -> String {
+        match self {
+            Self::Markdown => description.to_string(),
+            Self::PlainText => markdown::to_plain_text(description),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+/// Render Markdown as plain text for places that can't display it, like the
+/// TexTools wizard. Formatting markers are dropped, but lists keep their
+/// bullets and numbers, links keep their urls and top-level headings are
+/// underlined.
+pub fn to_plain_text(markdown: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+
+    // the next number for each list we're in, or none for bullet lists
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    // where the text of each link started, and where it points
+    let mut links: Vec<(usize, String)> = Vec::new();
+    let mut heading_start = 0;
+    let mut in_code_block = false;
+    // how many blockquotes we're in, and whether anything has been written
+    // in the outermost one yet
+    let mut quotes = 0;
+    let mut quote_started = false;
+    // whether we just wrote a list item's bullet
+    let mut item_started = false;
+
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    for event in Parser::new_ext(markdown, options) {
+        match event {
+            Event::Start(Tag::Paragraph) if !item_started => {
+                if quotes > 0 && quote_started {
+                    quote_break(&mut out, quotes);
+                } else {
+                    blank_line(&mut out);
+                }
+
+                quote_started = quotes > 0;
+                out.push_str(&"> ".repeat(quotes));
+                indent(&mut out, lists.len());
+            }
+            Event::Start(Tag::Heading { .. }) => {
+                blank_line(&mut out);
+                heading_start = out.len();
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let underline = match level {
+                    HeadingLevel::H1 => '=',
+                    HeadingLevel::H2 => '-',
+                    _ => continue,
+                };
+
+                let len = out[heading_start..].chars().count();
+                out.push('\n');
+                out.push_str(&underline.to_string().repeat(len));
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                if quotes == 0 {
+                    blank_line(&mut out);
+                } else if quote_started {
+                    quote_break(&mut out, quotes);
+                }
+
+                quotes += 1;
+            }
+            Event::End(TagEnd::BlockQuote) => {
+                quotes -= 1;
+                quote_started = quotes > 0;
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                blank_line(&mut out);
+                in_code_block = true;
+            }
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Start(Tag::List(start)) => {
+                if lists.is_empty() {
+                    blank_line(&mut out);
+                }
+
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                new_line(&mut out);
+                indent(&mut out, lists.len().saturating_sub(1));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        out.push_str(&format!("{number}. "));
+                        *number += 1;
+                    }
+                    _ => out.push_str("- "),
+                }
+
+                item_started = true;
+            }
+            Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. }) => {
+                links.push((out.len(), dest_url.into_string()));
+            }
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                if let Some((start, url)) = links.pop() {
+                    let text = out[start..].trim();
+                    let bare = url.strip_prefix("mailto:").unwrap_or(&url);
+                    if !url.is_empty() && text != url && text != bare {
+                        if text.is_empty() {
+                            out.push_str(&url);
+                        } else {
+                            out.push_str(&format!(" ({url})"));
+                        }
+                    }
+                }
+            }
+            Event::Text(text) => {
+                if in_code_block {
+                    for line in text.lines() {
+                        out.push_str("    ");
+                        out.push_str(line);
+                        out.push('\n');
+                    }
+                } else {
+                    out.push_str(&text);
+                }
+
+                item_started = false;
+            }
+            Event::Code(code) => {
+                out.push_str(&code);
+                item_started = false;
+            }
+            Event::SoftBreak => out.push(' '),
+            Event::HardBreak => {
+                out.push('\n');
+                out.push_str(&"> ".repeat(quotes));
+                indent(&mut out, lists.len());
+            }
+            Event::Rule => {
+                blank_line(&mut out);
+                out.push_str("----------");
+            }
+            Event::TaskListMarker(checked) => {
+                out.push_str(if checked { "[x] " } else { "[ ] " });
+            }
+            _ => {}
+        }
+    }
+
+    out.trim_end().to_string()
+}
+
+fn new_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Separate two blocks inside a blockquote without ending it.
+fn quote_break(out: &mut String, depth: usize) {
+    if out.ends_with(">\n") {
+        return;
+    }
+
+    new_line(out);
+    out.push_str("> ".repeat(depth).trim_end());
+    out.push('\n');
+}
+
+fn blank_line(out: &mut String) {
+    if out.is_empty() {
+        return;
+    }
+
+    while !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+fn indent(out: &mut String, level: usize) {
+    for _ in 0..level {
+        out.push_str("  ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings() {
+        assert_eq!(
+            to_plain_text("# Title\n\nText\n\n## Sub\n\nMore\n\n### Small\n\nEnd"),
+            "Title\n=====\n\nText\n\nSub\n---\n\nMore\n\nSmall\n\nEnd",
+        );
+    }
+
+    #[test]
+    fn bullet_lists() {
+        assert_eq!(to_plain_text("- one\n- two\n  - nested\n- three"), "- one\n- two\n  - nested\n- three");
+        assert_eq!(to_plain_text("- [x] done\n- [ ] todo"), "- [x] done\n- [ ] todo");
+    }
+
+    #[test]
+    fn numbered_lists() {
+        assert_eq!(to_plain_text("1. one\n2. two\n3. three"), "1. one\n2. two\n3. three");
+        // lists keep the number they start at
+        assert_eq!(to_plain_text("3. three\n4. four"), "3. three\n4. four");
+    }
+
+    #[test]
+    fn links_keep_their_url() {
+        assert_eq!(to_plain_text("See [the site](https://example.com)."), "See the site (https://example.com).");
+        assert_eq!(to_plain_text("![alt](https://example.com/a.png)"), "alt (https://example.com/a.png)");
+        // the url isn't repeated if it's already the text
+        assert_eq!(to_plain_text("<https://example.com> or <me@example.com>"), "https://example.com or me@example.com");
+    }
+
+    #[test]
+    fn emphasis_is_stripped() {
+        assert_eq!(to_plain_text("Some *emphasis*, **strong**, ~~gone~~ and `code`."), "Some emphasis, strong, gone and code.");
+    }
+
+    #[test]
+    fn blockquote_paragraphs() {
+        assert_eq!(to_plain_text("> first\n>\n> second"), "> first\n>\n> second");
+        assert_eq!(to_plain_text("before\n\n> first\n>\n> second\n\nafter"), "before\n\n> first\n>\n> second\n\nafter");
+    }
+
+    #[test]
+    fn nested_blockquotes() {
+        assert_eq!(to_plain_text("> one\n>\n> > nested\n>\n> back"), "> one\n>\n> > nested\n>\n> back");
+        assert_eq!(to_plain_text("> > first\n> >\n> > second"), "> > first\n> >\n> > second");
+    }
+
+    #[test]
+    fn separate_blockquotes() {
+        assert_eq!(to_plain_text("> first\n\n> second"), "> first\n\n> second");
+    }
+
+    #[test]
+    fn blockquote_hard_breaks() {
+        assert_eq!(to_plain_text("text\n\n> a  \n> b"), "text\n\n> a\n> b");
+    }
+}
@@ -0,0 +1,793 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// A Penumbra meta manipulation, as found in option json and in
+/// Heliosphere's needed files.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(tag = "Type", content = "Manipulation")]
+pub enum Manipulation {
+    Imc(ImcManipulation),
+    Eqdp(EqdpManipulation),
+    Eqp(EqpManipulation),
+    Est(EstManipulation),
+    Gmp(GmpManipulation),
+    Rsp(RspManipulation),
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImcManipulation {
+    pub entry: ImcEntry,
+    pub object_type: String,
+    pub primary_id: u16,
+    #[serde(default)]
+    pub secondary_id: u16,
+    pub variant: u8,
+    #[serde(default = "unknown")]
+    pub equip_slot: String,
+    #[serde(default = "unknown")]
+    pub body_slot: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImcEntry {
+    pub material_id: u8,
+    pub decal_id: u8,
+    pub vfx_id: u8,
+    pub material_animation_id: u8,
+    pub attribute_mask: u16,
+    pub sound_id: u8,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct EqdpManipulation {
+    /// The whole EQDP entry for the set, the way Penumbra stores it, so this
+    /// slot's two bits sit at the slot's position and bits for any other
+    /// slot are ignored.
+    pub entry: u32,
+    pub gender: String,
+    pub race: String,
+    pub set_id: u16,
+    pub slot: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct EqpManipulation {
+    pub entry: u64,
+    pub set_id: u16,
+    pub slot: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct EstManipulation {
+    pub entry: u16,
+    pub gender: String,
+    pub race: String,
+    pub set_id: u16,
+    pub slot: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct GmpManipulation {
+    pub entry: GmpEntry,
+    pub set_id: u16,
+}
+
+/// Older versions of Penumbra store the raw value, newer ones split it up.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum GmpEntry {
+    Raw(u64),
+    Fields(GmpFields),
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "PascalCase")]
+pub struct GmpFields {
+    pub enabled: bool,
+    pub animated: bool,
+    pub rotation_a: u16,
+    pub rotation_b: u16,
+    pub rotation_c: u16,
+    pub unknown_a: u8,
+    pub unknown_b: u8,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct RspManipulation {
+    pub entry: f32,
+    pub sub_race: String,
+    pub attribute: String,
+}
+
+fn unknown() -> String {
+    "Unknown".into()
+}
+
+impl GmpEntry {
+    pub fn value(&self) -> u64 {
+        match *self {
+            Self::Raw(value) => value & 0xFF_FFFF_FFFF,
+            Self::Fields(f) => f.enabled as u64
+                | (f.animated as u64) << 1
+                | (f.rotation_a as u64 & 0x3FF) << 2
+                | (f.rotation_b as u64 & 0x3FF) << 12
+                | (f.rotation_c as u64 & 0x3FF) << 22
+                | (f.unknown_a as u64 & 0xF) << 32
+                | (f.unknown_b as u64 & 0xF) << 36,
+        }
+    }
+}
+
+/// The slots TexTools knows about, with their file suffix, their byte
+/// range inside an EQP entry and their bit offset inside an EQDP entry.
+struct Slot {
+    penumbra: &'static str,
+    suffix: &'static str,
+    accessory: bool,
+    eqp: Option<(usize, usize)>,
+    eqdp_shift: u32,
+}
+
+const SLOTS: &[Slot] = &[
+    Slot { penumbra: "Head", suffix: "met", accessory: false, eqp: Some((5, 3)), eqdp_shift: 0 },
+    Slot { penumbra: "Body", suffix: "top", accessory: false, eqp: Some((0, 2)), eqdp_shift: 2 },
+    Slot { penumbra: "Hands", suffix: "glv", accessory: false, eqp: Some((3, 1)), eqdp_shift: 4 },
+    Slot { penumbra: "Legs", suffix: "dwn", accessory: false, eqp: Some((2, 1)), eqdp_shift: 6 },
+    Slot { penumbra: "Feet", suffix: "sho", accessory: false, eqp: Some((4, 1)), eqdp_shift: 8 },
+    Slot { penumbra: "Ears", suffix: "ear", accessory: true, eqp: None, eqdp_shift: 0 },
+    Slot { penumbra: "Neck", suffix: "nek", accessory: true, eqp: None, eqdp_shift: 2 },
+    Slot { penumbra: "Wrists", suffix: "wrs", accessory: true, eqp: None, eqdp_shift: 4 },
+    Slot { penumbra: "RFinger", suffix: "rir", accessory: true, eqp: None, eqdp_shift: 6 },
+    Slot { penumbra: "LFinger", suffix: "ril", accessory: true, eqp: None, eqdp_shift: 8 },
+];
+
+fn slot(name: &str) -> anyhow::Result<&'static Slot> {
+    SLOTS.iter()
+        .find(|slot| slot.penumbra == name)
+        .with_context(|| format!("unsupported equipment slot {name}"))
+}
+
+const RACES: &[(&str, u16)] = &[
+    ("Midlander", 1),
+    ("Highlander", 3),
+    ("Elezen", 5),
+    ("Miqote", 7),
+    ("Roegadyn", 9),
+    ("Lalafell", 11),
+    ("AuRa", 13),
+    ("Hrothgar", 15),
+    ("Viera", 17),
+];
+
+/// Turn a Penumbra gender and model race into the numeric race code used by
+/// the game and TexTools, e.g. `0101` for a male Midlander.
+fn gender_race(gender: &str, race: &str) -> anyhow::Result<u16> {
+    let base = RACES.iter()
+        .find(|(name, _)| *name == race)
+        .map(|(_, base)| *base)
+        .with_context(|| format!("unsupported race {race}"))?;
+
+    let (female, npc) = match gender {
+        "Male" => (0, false),
+        "Female" => (1, false),
+        "MaleNpc" => (0, true),
+        "FemaleNpc" => (1, true),
+        _ => anyhow::bail!("unsupported gender {gender}"),
+    };
+
+    Ok((base + female) * 100 + if npc { 4 } else { 1 })
+}
+
+const SUB_RACES: &[&str] = &[
+    "Midlander",
+    "Highlander",
+    "Wildwood",
+    "Duskwight",
+    "Plainsfolk",
+    "Dunesfolk",
+    "SeekerOfTheSun",
+    "KeeperOfTheMoon",
+    "Seawolf",
+    "Hellsguard",
+    "Raen",
+    "Xaela",
+    "Hellion",
+    "Lost",
+    "Rava",
+    "Veena",
+];
+
+const RSP_MALE: &[&str] = &["MaleMinSize", "MaleMaxSize", "MaleMinTail", "MaleMaxTail"];
+
+const RSP_FEMALE: &[&str] = &[
+    "FemaleMinSize",
+    "FemaleMaxSize",
+    "FemaleMinTail",
+    "FemaleMaxTail",
+    "BustMinX",
+    "BustMinY",
+    "BustMinZ",
+    "BustMaxX",
+    "BustMaxY",
+    "BustMaxZ",
+];
+
+const META_VERSION: u32 = 2;
+const RGSP_VERSION: u16 = 2;
+
+const META_IMC: u32 = 1;
+const META_EQDP: u32 = 2;
+const META_EQP: u32 = 3;
+const META_EST: u32 = 4;
+const META_GMP: u32 = 5;
+
+/// Everything TexTools stores in one `.meta` file.
+#[derive(Default)]
+struct MetaFile {
+    eqp_slot: Option<&'static Slot>,
+    imc: BTreeMap<u8, ImcEntry>,
+    eqdp: BTreeMap<u16, u8>,
+    eqp: Option<u64>,
+    est: BTreeMap<u16, (u16, u16)>,
+    gmp: Option<u64>,
+}
+
+impl MetaFile {
+    fn to_bytes(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let mut blocks: Vec<(u32, Vec<u8>)> = Vec::new();
+
+        if let Some(max) = self.imc.keys().max().copied() {
+            // TexTools stores every variant up to the last one and applies
+            // all of them, so we can't make up the ones the author didn't set
+            let missing: Vec<String> = (0..=max)
+                .filter(|variant| !self.imc.contains_key(variant))
+                .map(|variant| variant.to_string())
+                .collect();
+            if !missing.is_empty() {
+                anyhow::bail!(
+                    "imc manipulations for {path} must set every variant up to {max} to be exported to TexTools, but variants {} are missing",
+                    missing.join(", "),
+                );
+            }
+
+            let mut data = Vec::with_capacity((max as usize + 1) * 6);
+            for entry in self.imc.values() {
+                data.push(entry.material_id);
+                data.push(entry.decal_id);
+                let mask = (entry.attribute_mask & 0x3FF) | ((entry.sound_id as u16 & 0x3F) << 10);
+                data.extend_from_slice(&mask.to_le_bytes());
+                data.push(entry.vfx_id);
+                data.push(entry.material_animation_id);
+            }
+
+            blocks.push((META_IMC, data));
+        }
+
+        if !self.eqdp.is_empty() {
+            let mut data = Vec::with_capacity(self.eqdp.len() * 5);
+            for (race, bits) in &self.eqdp {
+                data.extend_from_slice(&(*race as u32).to_le_bytes());
+                data.push(*bits);
+            }
+
+            blocks.push((META_EQDP, data));
+        }
+
+        if let (Some(entry), Some((offset, size))) = (self.eqp, self.eqp_slot.and_then(|slot| slot.eqp)) {
+            let bytes = entry.to_le_bytes();
+            blocks.push((META_EQP, bytes[offset..offset + size].to_vec()));
+        }
+
+        if !self.est.is_empty() {
+            let mut data = Vec::with_capacity(self.est.len() * 6);
+            for (race, (set_id, entry)) in &self.est {
+                data.extend_from_slice(&race.to_le_bytes());
+                data.extend_from_slice(&set_id.to_le_bytes());
+                data.extend_from_slice(&entry.to_le_bytes());
+            }
+
+            blocks.push((META_EST, data));
+        }
+
+        if let Some(entry) = self.gmp {
+            blocks.push((META_GMP, entry.to_le_bytes()[..5].to_vec()));
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&META_VERSION.to_le_bytes());
+        bytes.extend_from_slice(path.as_bytes());
+        bytes.push(0);
+
+        let header_start = bytes.len() + 12;
+        bytes.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&12u32.to_le_bytes());
+        bytes.extend_from_slice(&(header_start as u32).to_le_bytes());
+
+        let mut offset = header_start + blocks.len() * 12;
+        for (kind, data) in &blocks {
+            bytes.extend_from_slice(&kind.to_le_bytes());
+            bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            offset += data.len();
+        }
+
+        for (_, data) in blocks {
+            bytes.extend_from_slice(&data);
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn equipment_meta_path(set_id: u16, slot: &Slot) -> String {
+    if slot.accessory {
+        format!("chara/accessory/a{set_id:04}/a{set_id:04}_{}.meta", slot.suffix)
+    } else {
+        format!("chara/equipment/e{set_id:04}/e{set_id:04}_{}.meta", slot.suffix)
+    }
+}
+
+fn imc_meta_path(imc: &ImcManipulation) -> anyhow::Result<(String, Option<&'static Slot>)> {
+    let (primary, secondary) = (imc.primary_id, imc.secondary_id);
+    let path = match imc.object_type.as_str() {
+        "Equipment" | "Accessory" => {
+            let slot = slot(&imc.equip_slot)?;
+            return Ok((equipment_meta_path(primary, slot), Some(slot)));
+        }
+        "DemiHuman" => {
+            let slot = slot(&imc.equip_slot)?;
+            format!("chara/demihuman/d{primary:04}/obj/equipment/e{secondary:04}/e{secondary:04}_{}.meta", slot.suffix)
+        }
+        "Weapon" => format!("chara/weapon/w{primary:04}/obj/body/b{secondary:04}/b{secondary:04}.meta"),
+        "Monster" => format!("chara/monster/m{primary:04}/obj/body/b{secondary:04}/b{secondary:04}.meta"),
+        other => anyhow::bail!("unsupported imc object type {other}"),
+    };
+
+    Ok((path, None))
+}
+
+/// Generate the TexTools `.meta` and `.rgsp` files that represent the given
+/// manipulations. Returns pairs of game path and file contents.
+///
+/// TexTools metadata files contain complete entries, so the manipulations
+/// must describe every value that should end up in them, including every IMC
+/// variant up to the last one that's changed.
+pub fn textools_files(manipulations: &[Manipulation]) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let mut metas: BTreeMap<String, MetaFile> = BTreeMap::new();
+    let mut rsps: BTreeMap<(usize, u8), BTreeMap<&str, f32>> = BTreeMap::new();
+
+    for manipulation in manipulations {
+        match manipulation {
+            Manipulation::Imc(imc) => {
+                let (path, slot) = imc_meta_path(imc)?;
+                let meta = metas.entry(path).or_default();
+                meta.eqp_slot = meta.eqp_slot.or(slot);
+                meta.imc.insert(imc.variant, imc.entry);
+            }
+            Manipulation::Eqdp(eqdp) => {
+                let slot = slot(&eqdp.slot)?;
+                let race = gender_race(&eqdp.gender, &eqdp.race)?;
+                let bits = eqdp.entry >> slot.eqdp_shift;
+
+                let meta = metas.entry(equipment_meta_path(eqdp.set_id, slot)).or_default();
+                meta.eqp_slot = Some(slot);
+                meta.eqdp.insert(race, (bits & 3) as u8);
+            }
+            Manipulation::Eqp(eqp) => {
+                let slot = slot(&eqp.slot)?;
+                if slot.eqp.is_none() {
+                    anyhow::bail!("eqp manipulations are not supported for {}", eqp.slot);
+                }
+
+                let meta = metas.entry(equipment_meta_path(eqp.set_id, slot)).or_default();
+                meta.eqp_slot = Some(slot);
+                meta.eqp = Some(eqp.entry);
+            }
+            Manipulation::Est(est) => {
+                if !matches!(est.slot.as_str(), "Head" | "Body") {
+                    anyhow::bail!("est manipulations for {} cannot be represented in TexTools", est.slot);
+                }
+
+                let slot = slot(&est.slot)?;
+                let race = gender_race(&est.gender, &est.race)?;
+                let meta = metas.entry(equipment_meta_path(est.set_id, slot)).or_default();
+                meta.eqp_slot = Some(slot);
+                meta.est.insert(race, (est.set_id, est.entry));
+            }
+            Manipulation::Gmp(gmp) => {
+                let slot = slot("Head")?;
+                let meta = metas.entry(equipment_meta_path(gmp.set_id, slot)).or_default();
+                meta.eqp_slot = Some(slot);
+                meta.gmp = Some(gmp.entry.value());
+            }
+            Manipulation::Rsp(rsp) => {
+                let sub_race = SUB_RACES.iter()
+                    .position(|name| *name == rsp.sub_race)
+                    .with_context(|| format!("unsupported sub race {}", rsp.sub_race))?;
+                let gender = if RSP_MALE.contains(&rsp.attribute.as_str()) {
+                    0
+                } else if RSP_FEMALE.contains(&rsp.attribute.as_str()) {
+                    1
+                } else {
+                    anyhow::bail!("unsupported rsp attribute {}", rsp.attribute);
+                };
+
+                rsps.entry((sub_race, gender))
+                    .or_default()
+                    .insert(rsp.attribute.as_str(), rsp.entry);
+            }
+        }
+    }
+
+    let mut files: Vec<_> = metas.into_iter()
+        .map(|(path, meta)| -> anyhow::Result<_> {
+            let bytes = meta.to_bytes(&path)?;
+            Ok((path, bytes))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    for ((sub_race, gender), values) in rsps {
+        let attributes = if gender == 0 { RSP_MALE } else { RSP_FEMALE };
+
+        let mut bytes = vec![0xFF];
+        bytes.extend_from_slice(&RGSP_VERSION.to_le_bytes());
+        bytes.push(sub_race as u8);
+        bytes.push(gender);
+        for attribute in attributes {
+            // rgsp files replace the whole entry, so we can't guess at values
+            // the author didn't set
+            let value = values.get(attribute).with_context(|| format!(
+                "rsp manipulations for {} must set {attribute} to be exported to TexTools",
+                SUB_RACES[sub_race],
+            ))?;
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // male files are padded to the same size as female ones
+        bytes.resize(5 + RSP_FEMALE.len() * 4, 0);
+
+        files.push((format!("chara/xls/charamake/rgsp/{sub_race}-{gender}.rgsp"), bytes));
+    }
+
+    Ok(files)
+}
+
+/// What a `.meta` file belongs to, worked out from its game path.
+struct MetaTarget {
+    object_type: &'static str,
+    primary_id: u16,
+    secondary_id: u16,
+    slot: Option<&'static Slot>,
+}
+
+fn parse_id(segment: &str, prefix: char) -> anyhow::Result<u16> {
+    segment.strip_prefix(prefix)
+        .and_then(|id| id.get(..4))
+        .and_then(|id| id.parse().ok())
+        .with_context(|| format!("invalid id {segment}"))
+}
+
+fn meta_target(path: &str) -> anyhow::Result<MetaTarget> {
+    let segments: Vec<&str> = path.split('/').collect();
+    let file_slot = || -> anyhow::Result<&'static Slot> {
+        let stem = segments[segments.len() - 1].trim_end_matches(".meta");
+        let suffix = stem.rsplit('_').next().unwrap_or_default();
+        SLOTS.iter()
+            .find(|slot| slot.suffix == suffix)
+            .with_context(|| format!("unsupported equipment slot {suffix}"))
+    };
+
+    let target = match segments.as_slice() {
+        ["chara", "equipment", set, _] => MetaTarget {
+            object_type: "Equipment",
+            primary_id: parse_id(set, 'e')?,
+            secondary_id: 0,
+            slot: Some(file_slot()?),
+        },
+        ["chara", "accessory", set, _] => MetaTarget {
+            object_type: "Accessory",
+            primary_id: parse_id(set, 'a')?,
+            secondary_id: 0,
+            slot: Some(file_slot()?),
+        },
+        ["chara", "demihuman", demi, "obj", "equipment", set, _] => MetaTarget {
+            object_type: "DemiHuman",
+            primary_id: parse_id(demi, 'd')?,
+            secondary_id: parse_id(set, 'e')?,
+            slot: Some(file_slot()?),
+        },
+        ["chara", "weapon", weapon, "obj", "body", body, _] => MetaTarget {
+            object_type: "Weapon",
+            primary_id: parse_id(weapon, 'w')?,
+            secondary_id: parse_id(body, 'b')?,
+            slot: None,
+        },
+        ["chara", "monster", monster, "obj", "body", body, _] => MetaTarget {
+            object_type: "Monster",
+            primary_id: parse_id(monster, 'm')?,
+            secondary_id: parse_id(body, 'b')?,
+            slot: None,
+        },
+        _ => anyhow::bail!("unsupported metadata file {path}"),
+    };
+
+    Ok(target)
+}
+
+/// The inverse of [`gender_race`].
+fn race_gender(code: u16) -> anyhow::Result<(&'static str, &'static str)> {
+    let tens = code / 100;
+    let female = tens & 1 == 0;
+    let base = if female { tens.checked_sub(1) } else { Some(tens) };
+    let race = RACES.iter()
+        .find(|(_, race_base)| Some(*race_base) == base)
+        .map(|(name, _)| *name)
+        .with_context(|| format!("unsupported race code {code:04}"))?;
+
+    let gender = match (female, code % 100) {
+        (false, 1) => "Male",
+        (true, 1) => "Female",
+        (false, 4) => "MaleNpc",
+        (true, 4) => "FemaleNpc",
+        _ => anyhow::bail!("unsupported race code {code:04}"),
+    };
+
+    Ok((gender, race))
+}
+
+fn read_u16(data: &[u8], at: usize) -> anyhow::Result<u16> {
+    data.get(at..at + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .context("metadata file is too short")
+}
+
+fn read_u32(data: &[u8], at: usize) -> anyhow::Result<u32> {
+    data.get(at..at + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .context("metadata file is too short")
+}
+
+/// Turn a TexTools `.meta` or `.rgsp` file back into the Penumbra
+/// manipulations it represents.
+///
+/// TexTools files contain complete entries, so every IMC variant and race in
+/// the file becomes a manipulation, even ones that match the game's values.
+pub fn manipulations(game_path: &str, data: &[u8]) -> anyhow::Result<Vec<Manipulation>> {
+    if game_path.ends_with(".rgsp") {
+        return rgsp_manipulations(data);
+    }
+
+    let target = meta_target(game_path)?;
+
+    // skip the version and the embedded path
+    let path_end = data.iter()
+        .skip(4)
+        .position(|&b| b == 0)
+        .context("metadata file is too short")?;
+    let header = 4 + path_end + 1;
+    let count = read_u32(data, header)? as usize;
+    let entry_size = read_u32(data, header + 4)? as usize;
+    let start = read_u32(data, header + 8)? as usize;
+
+    let mut manipulations = Vec::new();
+    for i in 0..count {
+        let at = start + i * entry_size;
+        let kind = read_u32(data, at)?;
+        let offset = read_u32(data, at + 4)? as usize;
+        let size = read_u32(data, at + 8)? as usize;
+        let block = data.get(offset..offset + size)
+            .context("metadata file is too short")?;
+
+        let slot_name = || target.slot
+            .map(|slot| slot.penumbra.to_string())
+            .context("metadata block is not supported for this item");
+
+        match kind {
+            META_IMC => {
+                for (variant, entry) in block.chunks_exact(6).enumerate() {
+                    let mask = u16::from_le_bytes([entry[2], entry[3]]);
+                    manipulations.push(Manipulation::Imc(ImcManipulation {
+                        entry: ImcEntry {
+                            material_id: entry[0],
+                            decal_id: entry[1],
+                            vfx_id: entry[4],
+                            material_animation_id: entry[5],
+                            attribute_mask: mask & 0x3FF,
+                            sound_id: (mask >> 10) as u8,
+                        },
+                        object_type: target.object_type.into(),
+                        primary_id: target.primary_id,
+                        secondary_id: target.secondary_id,
+                        variant: variant as u8,
+                        equip_slot: target.slot.map_or_else(unknown, |slot| slot.penumbra.into()),
+                        body_slot: if target.slot.is_some() { unknown() } else { "Body".into() },
+                    }));
+                }
+            }
+            META_EQDP => {
+                let shift = target.slot
+                    .map(|slot| slot.eqdp_shift)
+                    .context("eqdp data is not supported for this item")?;
+                for entry in block.chunks_exact(5) {
+                    let code = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                    let (gender, race) = race_gender(code as u16)?;
+                    manipulations.push(Manipulation::Eqdp(EqdpManipulation {
+                        entry: ((entry[4] & 3) as u32) << shift,
+                        gender: gender.into(),
+                        race: race.into(),
+                        set_id: target.primary_id,
+                        slot: slot_name()?,
+                    }));
+                }
+            }
+            META_EQP => {
+                let (offset, size) = target.slot
+                    .and_then(|slot| slot.eqp)
+                    .context("eqp data is not supported for this item")?;
+                let len = size.min(block.len());
+                let mut bytes = [0; 8];
+                bytes[offset..offset + len].copy_from_slice(&block[..len]);
+                manipulations.push(Manipulation::Eqp(EqpManipulation {
+                    entry: u64::from_le_bytes(bytes),
+                    set_id: target.primary_id,
+                    slot: slot_name()?,
+                }));
+            }
+            META_EST => {
+                for entry in block.chunks_exact(6) {
+                    let (gender, race) = race_gender(read_u16(entry, 0)?)?;
+                    manipulations.push(Manipulation::Est(EstManipulation {
+                        entry: read_u16(entry, 4)?,
+                        gender: gender.into(),
+                        race: race.into(),
+                        set_id: read_u16(entry, 2)?,
+                        slot: slot_name()?,
+                    }));
+                }
+            }
+            META_GMP => {
+                let mut bytes = [0; 8];
+                let len = block.len().min(5);
+                bytes[..len].copy_from_slice(&block[..len]);
+                manipulations.push(Manipulation::Gmp(GmpManipulation {
+                    entry: GmpEntry::Raw(u64::from_le_bytes(bytes)),
+                    set_id: target.primary_id,
+                }));
+            }
+            // unknown blocks are for data Penumbra doesn't handle
+            _ => {}
+        }
+    }
+
+    Ok(manipulations)
+}
+
+fn rgsp_manipulations(data: &[u8]) -> anyhow::Result<Vec<Manipulation>> {
+    // files from older versions of TexTools have no version header
+    let header = if data.first() == Some(&0xFF) { 3 } else { 0 };
+    let (sub_race, gender) = match data.get(header..header + 2) {
+        Some(&[sub_race, gender]) => (sub_race as usize, gender),
+        _ => anyhow::bail!("rgsp file is too short"),
+    };
+
+    let sub_race = SUB_RACES.get(sub_race)
+        .with_context(|| format!("unsupported sub race {sub_race}"))?;
+    let attributes = if gender == 0 { RSP_MALE } else { RSP_FEMALE };
+
+    let mut manipulations = Vec::with_capacity(attributes.len());
+    for (i, attribute) in attributes.iter().enumerate() {
+        let value = read_u32(data, header + 2 + i * 4)?;
+        manipulations.push(Manipulation::Rsp(RspManipulation {
+            entry: f32::from_bits(value),
+            sub_race: sub_race.to_string(),
+            attribute: attribute.to_string(),
+        }));
+    }
+
+    Ok(manipulations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Vec<Manipulation> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn round_trip(input: &[Manipulation]) -> Vec<(String, serde_json::Value)> {
+        textools_files(input).unwrap()
+            .into_iter()
+            .map(|(path, bytes)| {
+                let parsed = manipulations(&path, &bytes).unwrap();
+                (path, serde_json::to_value(parsed).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn meta_round_trip() {
+        let equipment = r#"[
+            {"Type":"Imc","Manipulation":{"Entry":{"MaterialId":1,"DecalId":0,"VfxId":0,"MaterialAnimationId":0,"AttributeMask":0,"SoundId":0},"ObjectType":"Equipment","PrimaryId":100,"SecondaryId":0,"Variant":0,"EquipSlot":"Body","BodySlot":"Unknown"}},
+            {"Type":"Imc","Manipulation":{"Entry":{"MaterialId":1,"DecalId":0,"VfxId":2,"MaterialAnimationId":0,"AttributeMask":5,"SoundId":3},"ObjectType":"Equipment","PrimaryId":100,"SecondaryId":0,"Variant":1,"EquipSlot":"Body","BodySlot":"Unknown"}},
+            {"Type":"Eqdp","Manipulation":{"Entry":12,"Gender":"Female","Race":"Viera","SetId":100,"Slot":"Body"}},
+            {"Type":"Eqp","Manipulation":{"Entry":1234,"SetId":100,"Slot":"Body"}},
+            {"Type":"Est","Manipulation":{"Entry":7,"Gender":"Male","Race":"AuRa","SetId":100,"Slot":"Body"}}
+        ]"#;
+        let gmp = r#"[{"Type":"Gmp","Manipulation":{"Entry":99,"SetId":5}}]"#;
+        let rsp = r#"[
+            {"Type":"Rsp","Manipulation":{"Entry":1.5,"SubRace":"Raen","Attribute":"MaleMinSize"}},
+            {"Type":"Rsp","Manipulation":{"Entry":1.5,"SubRace":"Raen","Attribute":"MaleMaxSize"}},
+            {"Type":"Rsp","Manipulation":{"Entry":1.5,"SubRace":"Raen","Attribute":"MaleMinTail"}},
+            {"Type":"Rsp","Manipulation":{"Entry":1.5,"SubRace":"Raen","Attribute":"MaleMaxTail"}}
+        ]"#;
+
+        let mut all = parse(equipment);
+        all.extend(parse(gmp));
+        all.extend(parse(rsp));
+
+        let to_value = |json| serde_json::to_value(parse(json)).unwrap();
+        assert_eq!(round_trip(&all), vec![
+            ("chara/equipment/e0005/e0005_met.meta".to_string(), to_value(gmp)),
+            ("chara/equipment/e0100/e0100_top.meta".to_string(), to_value(equipment)),
+            ("chara/xls/charamake/rgsp/10-0.rgsp".to_string(), to_value(rsp)),
+        ]);
+    }
+
+    #[test]
+    fn imc_gap_is_an_error() {
+        let imc = parse(r#"[
+            {"Type":"Imc","Manipulation":{"Entry":{"MaterialId":1,"DecalId":0,"VfxId":0,"MaterialAnimationId":0,"AttributeMask":0,"SoundId":0},"ObjectType":"Equipment","PrimaryId":100,"SecondaryId":0,"Variant":2,"EquipSlot":"Body","BodySlot":"Unknown"}}
+        ]"#);
+
+        let err = textools_files(&imc).unwrap_err().to_string();
+        assert!(err.contains("variants 0, 1 are missing"), "{err}");
+    }
+
+    #[test]
+    fn eqdp_uses_the_slots_bits_of_the_full_entry() {
+        // only the ring's bits are kept, the rest of the entry belongs to
+        // other slots
+        let eqdp = parse(r#"[
+            {"Type":"Eqdp","Manipulation":{"Entry":1023,"Gender":"Male","Race":"Midlander","SetId":50,"Slot":"RFinger"}}
+        ]"#);
+
+        let expected = parse(r#"[
+            {"Type":"Eqdp","Manipulation":{"Entry":192,"Gender":"Male","Race":"Midlander","SetId":50,"Slot":"RFinger"}}
+        ]"#);
+        assert_eq!(round_trip(&eqdp), vec![
+            ("chara/accessory/a0050/a0050_rir.meta".to_string(), serde_json::to_value(expected).unwrap()),
+        ]);
+    }
+
+    #[test]
+    fn eqdp_entry_for_the_lowest_slot() {
+        // bits for the lowest slot aren't shifted, and are kept for that slot
+        let head = parse(r#"[
+            {"Type":"Eqdp","Manipulation":{"Entry":2,"Gender":"Female","Race":"AuRa","SetId":7,"Slot":"Head"}}
+        ]"#);
+        assert_eq!(round_trip(&head), vec![
+            ("chara/equipment/e0007/e0007_met.meta".to_string(), serde_json::to_value(&head).unwrap()),
+        ]);
+
+        // but ignored for any other slot
+        let body = parse(r#"[
+            {"Type":"Eqdp","Manipulation":{"Entry":3,"Gender":"Female","Race":"AuRa","SetId":7,"Slot":"Body"}}
+        ]"#);
+        let expected = parse(r#"[
+            {"Type":"Eqdp","Manipulation":{"Entry":0,"Gender":"Female","Race":"AuRa","SetId":7,"Slot":"Body"}}
+        ]"#);
+        assert_eq!(round_trip(&body), vec![
+            ("chara/equipment/e0007/e0007_top.meta".to_string(), serde_json::to_value(expected).unwrap()),
+        ]);
+    }
+}
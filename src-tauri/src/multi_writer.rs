@@ -16,10 +16,12 @@ impl<W1, W2> MultiWriter<W1, W2> {
 
 impl<W1: Write, W2: Write> Write for MultiWriter<W1, W2> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let one = self.one.write(buf);
-        let two = self.two.write(buf);
+        // either writer might only take part of the buffer, so both have to
+        // take all of it to stay in step
+        self.one.write_all(buf)?;
+        self.two.write_all(buf)?;
 
-        one.and(two)
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -28,4 +30,42 @@ impl<W1: Write, W2: Write> Write for MultiWriter<W1, W2> {
 
         one.and(two)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::MultiWriter;
+
+    /// Takes at most a few bytes per write, like a writer under pressure.
+    struct Short(Vec<u8>);
+
+    impl Write for Short {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_writes_keep_both_in_step() {
+        let data: Vec<u8> = (0..=255).collect();
+
+        let mut one = Short(Vec::new());
+        let mut two = Vec::new();
+        std::io::copy(&mut data.as_slice(), &mut MultiWriter::new(&mut one, &mut two)).unwrap();
+        assert_eq!(one.0, data);
+        assert_eq!(two, data);
+
+        let mut one = Vec::new();
+        let mut two = Short(Vec::new());
+        std::io::copy(&mut data.as_slice(), &mut MultiWriter::new(&mut one, &mut two)).unwrap();
+        assert_eq!(one, data);
+        assert_eq!(two.0, data);
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use blake3::Hasher as Blake3;
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{Manager, Runtime, Window};
+use zip::{CompressionMethod, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::{pmp, verify};
+use crate::deduplicate::temp_file_for;
+use crate::error::Error;
+use crate::multi_writer::MultiWriter;
+use crate::pmp::PmpJsons;
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+enum PackProgress {
+    SettingUp,
+    AddingFiles {
+        current: usize,
+        total: usize,
+    },
+    Verifying,
+    Done {
+        report: PackReport,
+    },
+}
+
+impl PackProgress {
+    pub const EVENT: &'static str = "pack-progress";
+
+    pub fn emit<R: Runtime>(&self, window: &Window<R>) -> Result<(), Error> {
+        // this only fails if the window is gone, so there's no one to report to
+        window.emit_all(Self::EVENT, self)
+            .map_err(|_| Error::Cancelled)
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PackReport {
+    pub output_size: u64,
+    pub files: usize,
+    /// Files in the folder that nothing points to, relative to the folder.
+    pub unreferenced: Vec<String>,
+    /// Whether the unreferenced files were packed anyway.
+    pub kept_unreferenced: bool,
+}
+
+/// Pack a Penumbra mod folder into a PMP. Every file the options point to
+/// must exist, paths in the JSONs are rewritten to match the files on disk
+/// with forward slashes, and files nothing points to are left out unless
+/// `keep_unreferenced` is set.
+pub fn pack_pmp_inner<R: Runtime>(window: Window<R>, path: &str, output: Option<String>, keep_unreferenced: bool) -> anyhow::Result<PackReport> {
+    PackProgress::SettingUp.emit(&window)?;
+
+    let dir = Path::new(path);
+    // the folder name can have dots in it, so don't treat them as an extension
+    let destination = output.map(PathBuf::from)
+        .unwrap_or_else(|| {
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            dir.with_file_name(format!("{name}.pmp"))
+        });
+
+    // everything in the folder, by normalised path
+    let mut on_disk = Vec::new();
+    find_files(dir, dir, &mut on_disk)?;
+    let lookup: HashMap<String, &String> = on_disk.iter()
+        .map(|path| (pmp::normalise(path), path))
+        .collect();
+
+    let read = |name: &str| -> anyhow::Result<Value> {
+        let file = File::open(dir.join(name))
+            .map_err(|_| Error::MissingEntry(name.into()))?;
+        pmp::read_json(file, name)
+    };
+
+    let meta = read("meta.json")?;
+    let mut group_names: Vec<&String> = on_disk.iter()
+        .filter(|path| pmp::is_group(path))
+        .collect();
+    group_names.sort_unstable();
+
+    let mut jsons = PmpJsons {
+        default: read("default_mod.json")?,
+        groups: group_names.into_iter()
+            .map(|name| -> anyhow::Result<_> { Ok((name.clone(), read(name)?)) })
+            .collect::<anyhow::Result<_>>()?,
+    };
+
+    // point every file at the real file on disk
+    let mut referenced: BTreeMap<&String, Option<String>> = BTreeMap::new();
+    let mut missing = Vec::new();
+    for map in pmp::files_maps(&mut jsons) {
+        for (game_path, local) in map.files.iter_mut() {
+            let found = local.as_str().and_then(|local| lookup.get(&pmp::normalise(local)));
+            match found {
+                Some(path) => {
+                    *local = Value::String(path.to_string());
+                    referenced.insert(*path, None);
+                }
+                None => missing.push(format!("{} (for {game_path})", local.as_str().unwrap_or_default())),
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        missing.sort_unstable();
+        missing.dedup();
+        return Err(Error::MissingEntries(missing).into());
+    }
+
+    let images: Vec<String> = pmp::images(&jsons)
+        .chain(meta.get("Image").and_then(Value::as_str))
+        .map(pmp::normalise)
+        .collect();
+    let is_json = |path: &str| path == "meta.json" || path == "default_mod.json" || pmp::is_group(path);
+
+    let mut unreferenced = Vec::new();
+    let mut to_pack = Vec::new();
+    for path in &on_disk {
+        if is_json(path) {
+            continue;
+        }
+
+        let is_referenced = referenced.contains_key(path) || images.contains(&pmp::normalise(path));
+        if !is_referenced {
+            unreferenced.push(path.clone());
+            if !keep_unreferenced {
+                continue;
+            }
+        }
+
+        to_pack.push(path);
+    }
+
+    let temp = temp_file_for(&destination)?;
+    let mut zip = ZipWriter::new(temp);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let total = to_pack.len();
+    PackProgress::AddingFiles {
+        current: 0,
+        total,
+    }.emit(&window)?;
+
+    for (i, path) in to_pack.into_iter().enumerate() {
+        zip.start_file(path.as_str(), options)?;
+
+        let mut hasher = Blake3::new();
+        let mut file = BufReader::new(File::open(dir.join(path))?);
+        std::io::copy(&mut file, &mut MultiWriter::new(&mut zip, &mut hasher))?;
+        if let Some(hash) = referenced.get_mut(path) {
+            *hash = Some(data_encoding::BASE64URL_NOPAD.encode(hasher.finalize().as_bytes()));
+        }
+
+        PackProgress::AddingFiles {
+            current: i + 1,
+            total,
+        }.emit(&window)?;
+    }
+
+    zip.start_file("meta.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, &meta)?;
+    pmp::write_jsons(&mut zip, &jsons)?;
+
+    // the hash of every file, to check the new archive against
+    let mut expected = HashMap::new();
+    for map in pmp::files_maps(&mut jsons) {
+        for (game_path, local) in map.files.iter() {
+            let hash = local.as_str()
+                .and_then(|local| referenced.get(&local.to_string()))
+                .cloned()
+                .flatten();
+            if let Some(hash) = hash {
+                expected.insert((map.group.clone(), map.option.clone(), game_path.clone()), hash);
+            }
+        }
+    }
+
+    let temp = zip.finish()?;
+
+    PackProgress::Verifying.emit(&window)?;
+    verify::verify_pmp(temp.reopen()?, &expected)?;
+
+    let output_size = temp.as_file().metadata()?.len();
+    temp.persist(&destination)?;
+
+    let report = PackReport {
+        output_size,
+        files: total,
+        unreferenced,
+        kept_unreferenced: keep_unreferenced,
+    };
+
+    PackProgress::Done {
+        report: report.clone(),
+    }.emit(&window)?;
+
+    Ok(report)
+}
+
+/// Collect every file under `dir`, relative to `root` and with forward
+/// slashes.
+fn find_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            find_files(root, &path, files)?;
+            continue;
+        }
+
+        let relative: Vec<_> = path.strip_prefix(root)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        files.push(relative.join("/"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::delta::skip_bom;
+use crate::error::Error;
+
+/// The `meta.json` of a Penumbra modpack.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct PenumbraMeta {
+    pub file_version: u32,
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    pub version: String,
+    pub website: String,
+    pub mod_tags: Vec<String>,
+}
+
+/// The meta.json version Penumbra currently writes.
+pub const FILE_VERSION: u32 = 3;
+
+/// The option JSONs of a Penumbra modpack. These are kept as plain values so
+/// that rewriting them doesn't lose any fields we don't know about.
+pub struct PmpJsons {
+    pub default: Value,
+    /// Each group along with the name of its entry in the archive.
+    pub groups: Vec<(String, Value)>,
+}
+
+/// One of the `Files` maps in a modpack, from game path to archive path.
+pub struct FilesMap<'a> {
+    pub group: Option<String>,
+    pub option: Option<String>,
+    pub files: &'a mut Map<String, Value>,
+}
+
+pub fn is_group(name: &str) -> bool {
+    name.starts_with("group_") && name.ends_with(".json")
+}
+
+/// Archive paths are used case-insensitively and with either slash.
+pub fn normalise(path: &str) -> String {
+    path.replace('\\', "/").to_lowercase()
+}
+
+/// Make a name safe to use as a folder or file name.
+pub fn sanitise(name: &str) -> String {
+    let sanitised: String = name.chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let sanitised = sanitised.trim().trim_end_matches('.');
+
+    if sanitised.is_empty() {
+        "_".into()
+    } else {
+        sanitised.into()
+    }
+}
+
+/// Map the normalised name of every entry to its real name.
+pub fn entry_names<R: Read + Seek>(zip: &ZipArchive<R>) -> HashMap<String, String> {
+    zip.file_names()
+        .map(|name| (normalise(name), name.to_owned()))
+        .collect()
+}
+
+pub fn read_json<R: Read>(reader: R, name: &str) -> anyhow::Result<Value> {
+    let without_bom = skip_bom(reader)
+        .context("could not skip bom")?;
+    serde_json::from_reader(without_bom)
+        .with_context(|| format!("invalid pmp: invalid {name}"))
+}
+
+pub fn read_jsons<R: Read + Seek>(zip: &mut ZipArchive<R>) -> anyhow::Result<PmpJsons> {
+    let mut names: Vec<String> = zip.file_names().map(ToOwned::to_owned).collect();
+    if !names.iter().any(|name| name == "meta.json") {
+        return Err(Error::InvalidArchive("pmp is missing meta.json".into()).into());
+    }
+
+    let default = {
+        let file = zip.by_name("default_mod.json")
+            .map_err(|_| Error::MissingEntry("default_mod.json".into()))?;
+        read_json(file, "default_mod.json")?
+    };
+
+    names.retain(|name| is_group(name));
+    names.sort_unstable();
+
+    let mut groups = Vec::with_capacity(names.len());
+    for name in names {
+        let file = zip.by_name(&name)
+            .with_context(|| format!("could not get {name}"))?;
+        let group = read_json(file, &name)?;
+        groups.push((name, group));
+    }
+
+    Ok(PmpJsons {
+        default,
+        groups,
+    })
+}
+
+/// Write the option JSONs back under their original names, the way Penumbra
+/// writes them.
+pub fn write_jsons<W: Write + Seek>(zip: &mut ZipWriter<W>, jsons: &PmpJsons) -> anyhow::Result<()> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("default_mod.json", options)?;
+    serde_json::to_writer_pretty(&mut *zip, &jsons.default)?;
+
+    for (name, group) in &jsons.groups {
+        zip.start_file(name.as_str(), options)?;
+        serde_json::to_writer_pretty(&mut *zip, group)?;
+    }
+
+    Ok(())
+}
+
+/// Every `Files` map in the default option and the groups' options.
+pub fn files_maps(jsons: &mut PmpJsons) -> Vec<FilesMap<'_>> {
+    let mut maps = Vec::new();
+
+    if let Some(files) = jsons.default.get_mut("Files").and_then(Value::as_object_mut) {
+        maps.push(FilesMap {
+            group: None,
+            option: None,
+            files,
+        });
+    }
+
+    for (_, group) in &mut jsons.groups {
+        let group_name = group.get("Name").and_then(Value::as_str).map(ToOwned::to_owned);
+        let options = match group.get_mut("Options").and_then(Value::as_array_mut) {
+            Some(options) => options,
+            None => continue,
+        };
+
+        for option in options {
+            let option_name = option.get("Name").and_then(Value::as_str).map(ToOwned::to_owned);
+            if let Some(files) = option.get_mut("Files").and_then(Value::as_object_mut) {
+                maps.push(FilesMap {
+                    group: group_name.clone(),
+                    option: option_name,
+                    files,
+                });
+            }
+        }
+    }
+
+    maps
+}
+
+/// The images the groups point to.
+pub fn images(jsons: &PmpJsons) -> impl Iterator<Item = &str> {
+    jsons.groups.iter()
+        .filter_map(|(_, group)| group.get("Image").and_then(Value::as_str))
+        .filter(|image| !image.is_empty())
+}
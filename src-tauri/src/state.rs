@@ -8,9 +8,11 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::RwLock;
 
 use crate::UsageInfo;
+use crate::converters::xma::XmaSession;
 
 pub struct State {
     pub cache: RwLock<Cache>,
+    pub xma: RwLock<XmaSession>,
 }
 
 #[derive(Deserialize, Serialize, Default)]
@@ -24,6 +26,7 @@ impl State {
 
         Ok(Self {
             cache: RwLock::new(cache),
+            xma: RwLock::new(XmaSession::new(None)?),
         })
     }
 
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use ttmp::model::{ManifestKind, ModGroup, ModOption, ModPack, ModPackPage, SelectionType as TtmpSelectionType};
+use ttmp::ttmp_extractor::TtmpExtractor;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::{create_ttmp, verify};
+use crate::deduplicate::temp_file_for;
+use crate::error::Error;
+
+/// The name of the group made from the packs a v1 modpack was put together
+/// from.
+const INFERRED_GROUP: &str = "Modpacks";
+
+/// Metadata to use instead of what's in the modpack's entries.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UpgradeInfo {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    /// Turn each modpack the files came from into an option of a
+    /// multi-choice group, instead of making a simple modpack.
+    pub infer_groups: bool,
+}
+
+/// Rewrite a v1 TexTools modpack with a v2 manifest. The data file and any
+/// other entries are copied over as they are, since the files don't move.
+pub fn upgrade_ttmp_inner(path: &str, output: Option<String>, info: UpgradeInfo) -> anyhow::Result<()> {
+    let path = Path::new(path);
+    let destination = output.map(PathBuf::from)
+        .unwrap_or_else(|| path.with_extension("v2.ttmp2"));
+
+    let mods = {
+        let extractor = TtmpExtractor::new(File::open(path)?)
+            .map_err(|e| Error::InvalidArchive(e.to_string()))?;
+        match extractor.manifest() {
+            ManifestKind::V1(mods) => mods.clone(),
+            ManifestKind::V2(_) => return Err(Error::UnsupportedFormat("modpack already has a v2 manifest".into()).into()),
+        }
+    };
+
+    // the hash of every file, to check the new archive against
+    let original = file_hashes(File::open(path)?)?;
+
+    let entry = mods.iter().find_map(|mod_| mod_.mod_pack_entry.as_ref());
+    let name = info.name
+        .or_else(|| entry.map(|entry| entry.name.clone()))
+        .or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let author = info.author
+        .or_else(|| entry.map(|entry| entry.author.clone()))
+        .unwrap_or_default();
+    let version = info.version
+        .or_else(|| entry.map(|entry| entry.version.clone()))
+        .unwrap_or_default();
+    let has_metadata = mods.iter()
+        .any(|mod_| mod_.full_path.ends_with(".meta") || mod_.full_path.ends_with(".rgsp"));
+
+    // group the files by the modpack they came from, in order of appearance
+    let mut simple_mods = Vec::new();
+    let mut options: Vec<ModOption> = Vec::new();
+    for mod_ in mods {
+        let source = mod_.mod_pack_entry.as_ref()
+            .filter(|_| info.infer_groups)
+            .map(|entry| entry.name.clone());
+
+        let source = match source {
+            Some(source) => source,
+            None => {
+                simple_mods.push(mod_);
+                continue;
+            }
+        };
+
+        match options.iter_mut().find(|option| option.name == source) {
+            Some(option) => option.mods_jsons.push(mod_),
+            None => options.push(ModOption {
+                name: source,
+                description: None,
+                image_path: None,
+                mods_jsons: vec![mod_],
+                group_name: INFERRED_GROUP.into(),
+                selection_type: TtmpSelectionType::Multi,
+                is_checked: true,
+            }),
+        }
+    }
+
+    let pages = if options.is_empty() {
+        None
+    } else {
+        Some(vec![ModPackPage {
+            page_index: 0,
+            mod_groups: vec![ModGroup {
+                group_name: INFERRED_GROUP.into(),
+                option_list: options,
+                selection_type: TtmpSelectionType::Multi,
+            }],
+        }])
+    };
+
+    let pack = ModPack {
+        ttmp_version: if pages.is_some() { "1.3w" } else { "1.3s" }.into(),
+        minimum_framework_version: Some(create_ttmp::minimum_framework_version(has_metadata).into()),
+        name,
+        author,
+        version,
+        description: info.description,
+        url: info.url,
+        mod_pack_pages: pages,
+        simple_mods_list: Some(simple_mods),
+    };
+
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let temp = temp_file_for(&destination)?;
+    let mut new_zip = ZipWriter::new(temp);
+
+    new_zip.start_file("TTMPL.mpl", SimpleFileOptions::default().compression_method(CompressionMethod::Deflated))?;
+    serde_json::to_writer(&mut new_zip, &pack)?;
+
+    // everything else stays as it was
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i)?;
+        if entry.name() == "TTMPL.mpl" {
+            continue;
+        }
+
+        new_zip.raw_copy_file(entry)?;
+    }
+
+    drop(zip);
+
+    let temp = new_zip.finish()?;
+
+    // every file should still point at the same data
+    let written = file_hashes(temp.reopen()?)?;
+    let mut problems = Vec::new();
+    for (key, hash) in &original {
+        match written.get(key) {
+            Some(written) if written == hash => {}
+            Some(_) => problems.push(format!("{} differs from the original", key.0)),
+            None => problems.push(format!("{} is missing", key.0)),
+        }
+    }
+
+    for key in written.keys() {
+        if !original.contains_key(key) {
+            problems.push(format!("{} was not expected", key.0));
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(Error::VerificationFailed(problems).into());
+    }
+
+    temp.persist(&destination)?;
+
+    Ok(())
+}
+
+/// Hash every file in a TexTools modpack by its game path and where its data
+/// starts. V1 modpacks can list the same game path more than once, and the
+/// data file is copied over as it is, so the offsets stay the same. The
+/// number of entries sharing a key is kept too, so dropping one is noticed.
+fn file_hashes<R: Read + Seek>(reader: R) -> anyhow::Result<BTreeMap<(String, usize), (String, usize)>> {
+    let extractor = TtmpExtractor::new(reader)
+        .map_err(|e| Error::InvalidArchive(e.to_string()))?;
+    let files = extractor.all_files_sorted();
+    let mut zip = extractor.zip().borrow_mut();
+    let mut data = zip.by_name("TTMPD.mpd")
+        .map_err(|_| Error::MissingEntry("TTMPD.mpd".into()))?;
+    let mut staging = tempfile::tempfile()?;
+
+    let mut hashes: BTreeMap<(String, usize), (String, usize)> = BTreeMap::new();
+    for file in files {
+        let key = (file.file.full_path.clone(), file.file.mod_offset);
+        if let Some((_, count)) = hashes.get_mut(&key) {
+            *count += 1;
+            continue;
+        }
+
+        staging.rewind()?;
+        staging.set_len(0)?;
+        TtmpExtractor::extract_one_into(&file, &mut data, &mut staging)?;
+        staging.rewind()?;
+        hashes.insert(key, (verify::content_hash(&mut staging)?, 1));
+    }
+
+    Ok(hashes)
+}
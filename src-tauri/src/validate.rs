@@ -0,0 +1,314 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use zip::ZipArchive;
+
+use crate::{game_path, pmp};
+use crate::delta::{GroupKind, PenumbraGroup, PenumbraStandardOptionSimple};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    /// The pack won't work as intended.
+    Error,
+    /// The pack works, but probably not how the author meant it to.
+    Warning,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum IssueKind {
+    InvalidJson,
+    MissingFile,
+    UnreferencedFile,
+    InvalidGamePath,
+    UppercaseGamePath,
+    DuplicateGroup,
+    DuplicateOption,
+    InvalidDefaultSettings,
+    EmptyGroup,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IssueLocation {
+    /// The archive entry the issue was found in.
+    pub entry: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub option: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_path: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Issue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+    pub location: IssueLocation,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+struct Validator {
+    issues: Vec<Issue>,
+}
+
+impl Validator {
+    fn push(&mut self, severity: Severity, kind: IssueKind, location: IssueLocation, message: impl Into<String>) {
+        self.issues.push(Issue {
+            severity,
+            kind,
+            location,
+            message: message.into(),
+        });
+    }
+
+    /// Parse a JSON entry, reporting it if that fails.
+    fn parse<T: DeserializeOwned>(&mut self, zip: &mut ZipArchive<File>, name: &str) -> Option<T> {
+        let location = || IssueLocation {
+            entry: name.into(),
+            ..Default::default()
+        };
+
+        let file = match zip.by_name(name) {
+            Ok(file) => file,
+            Err(_) => {
+                self.push(Severity::Error, IssueKind::MissingFile, location(), format!("{name} is missing"));
+                return None;
+            }
+        };
+
+        match pmp::read_json(file, name) {
+            Ok(value) => self.deserialize(name, value),
+            Err(e) => {
+                self.push(Severity::Error, IssueKind::InvalidJson, location(), format!("{e:#}"));
+                None
+            }
+        }
+    }
+
+    /// Turn an entry's JSON into `T`, reporting it if that fails.
+    fn deserialize<T: DeserializeOwned>(&mut self, name: &str, value: Value) -> Option<T> {
+        match serde_json::from_value(value) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.push(Severity::Error, IssueKind::InvalidJson, IssueLocation {
+                    entry: name.into(),
+                    ..Default::default()
+                }, e.to_string());
+                None
+            }
+        }
+    }
+
+    fn check_option(&mut self, entries: &HashSet<String>, referenced: &mut HashSet<String>, entry: &str, group: Option<&str>, option: Option<&str>, simple: &PenumbraStandardOptionSimple) {
+        let location = |game_path: &str| IssueLocation {
+            entry: entry.into(),
+            group: group.map(ToOwned::to_owned),
+            option: option.map(ToOwned::to_owned),
+            game_path: Some(game_path.into()),
+        };
+
+        let mut files: Vec<_> = simple.files.iter().collect();
+        files.sort_unstable();
+        for (game_path, local) in files {
+            let normalised = pmp::normalise(local);
+            if !entries.contains(&normalised) {
+                self.push(Severity::Error, IssueKind::MissingFile, location(game_path), format!("{local} is not in the pack"));
+            }
+            referenced.insert(normalised);
+
+            self.check_game_path(location(game_path), game_path);
+        }
+
+        let mut swaps: Vec<_> = simple.file_swaps.iter().collect();
+        swaps.sort_unstable();
+        for (from, to) in swaps {
+            self.check_game_path(location(from), from);
+            self.check_game_path(location(from), to);
+        }
+    }
+
+    fn check_game_path(&mut self, location: IssueLocation, game_path: &str) {
+        if let Err(reason) = game_path::dat_file(game_path) {
+            self.push(Severity::Error, IssueKind::InvalidGamePath, location, format!("{game_path} is not a valid game path: {reason}"));
+        } else if game_path.chars().any(char::is_uppercase) {
+            self.push(Severity::Warning, IssueKind::UppercaseGamePath, location, format!("{game_path} should be lowercase"));
+        }
+    }
+
+    fn check_group(&mut self, entry: &str, group: &PenumbraGroup, option_names: &[&str]) {
+        let location = || IssueLocation {
+            entry: entry.into(),
+            group: Some(group.name.clone()),
+            ..Default::default()
+        };
+
+        let mut seen = HashSet::new();
+        for name in option_names {
+            if !seen.insert(name) {
+                self.push(Severity::Warning, IssueKind::DuplicateOption, IssueLocation {
+                    option: Some(name.to_string()),
+                    ..location()
+                }, format!("option {name} appears more than once"));
+            }
+        }
+
+        let count = option_names.len();
+        match &group.kind {
+            GroupKind::Single { .. } if count == 0 => {
+                self.push(Severity::Error, IssueKind::EmptyGroup, location(), "single-choice group has no options");
+            }
+            GroupKind::Single { .. } => {
+                if group.default_settings as usize >= count {
+                    self.push(
+                        Severity::Error,
+                        IssueKind::InvalidDefaultSettings,
+                        location(),
+                        format!("default option {} does not exist, the group has {count} options", group.default_settings + 1),
+                    );
+                }
+            }
+            GroupKind::Multi { .. } | GroupKind::Imc { .. } => {
+                let valid = if count >= 32 { u32::MAX } else { (1 << count) - 1 };
+                if group.default_settings & !valid != 0 {
+                    self.push(
+                        Severity::Warning,
+                        IssueKind::InvalidDefaultSettings,
+                        location(),
+                        format!("default settings {:#b} enable options that don't exist, the group has {count} options", group.default_settings),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Collect the files a group or option refers to straight from its JSON, for
+/// when it can't be parsed.
+fn raw_references(value: &Value, referenced: &mut HashSet<String>) {
+    if let Some(image) = value.get("Image").and_then(Value::as_str) {
+        referenced.insert(pmp::normalise(image));
+    }
+
+    if let Some(files) = value.get("Files").and_then(Value::as_object) {
+        referenced.extend(files.values().filter_map(Value::as_str).map(pmp::normalise));
+    }
+
+    if let Some(options) = value.get("Options").and_then(Value::as_array) {
+        for option in options {
+            raw_references(option, referenced);
+        }
+    }
+}
+
+/// Check a Penumbra modpack for problems that would otherwise only show up
+/// once it's uploaded or installed.
+pub fn validate_pmp_inner(path: &str) -> anyhow::Result<ValidationReport> {
+    let mut zip = ZipArchive::new(File::open(Path::new(path))?)?;
+    let entries: HashSet<String> = zip.file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(pmp::normalise)
+        .collect();
+
+    let mut validator = Validator {
+        issues: Vec::new(),
+    };
+    let mut referenced = HashSet::new();
+
+    let meta: Option<Value> = validator.parse(&mut zip, "meta.json");
+    if let Some(image) = meta.as_ref().and_then(|meta| meta.get("Image")).and_then(Value::as_str) {
+        referenced.insert(pmp::normalise(image));
+    }
+
+    if let Some(value) = validator.parse::<Value>(&mut zip, "default_mod.json") {
+        match validator.deserialize::<PenumbraStandardOptionSimple>("default_mod.json", value.clone()) {
+            Some(default) => validator.check_option(&entries, &mut referenced, "default_mod.json", None, None, &default),
+            None => raw_references(&value, &mut referenced),
+        }
+    }
+
+    let mut group_paths: Vec<String> = zip.file_names()
+        .filter(|name| pmp::is_group(name))
+        .map(ToOwned::to_owned)
+        .collect();
+    group_paths.sort_unstable();
+
+    let mut group_names = HashSet::new();
+    for entry in &group_paths {
+        let value: Value = match validator.parse(&mut zip, entry) {
+            Some(value) => value,
+            None => continue,
+        };
+        let group: PenumbraGroup = match validator.deserialize(entry, value.clone()) {
+            Some(group) => group,
+            None => {
+                // the error is already reported, but the files the group
+                // uses shouldn't also show up as unused
+                raw_references(&value, &mut referenced);
+                continue;
+            }
+        };
+
+        if !group_names.insert(group.name.clone()) {
+            validator.push(Severity::Error, IssueKind::DuplicateGroup, IssueLocation {
+                entry: entry.clone(),
+                group: Some(group.name.clone()),
+                ..Default::default()
+            }, format!("group {} appears more than once", group.name));
+        }
+
+        if let Some(image) = &group.image {
+            referenced.insert(pmp::normalise(image));
+        }
+
+        let option_names: Vec<&str> = match &group.kind {
+            GroupKind::Single { options } | GroupKind::Multi { options } => {
+                for option in options {
+                    validator.check_option(&entries, &mut referenced, entry, Some(&group.name), Some(&option.name), &option.simple);
+                }
+
+                options.iter().map(|option| option.name.as_str()).collect()
+            }
+            GroupKind::Imc { options, .. } => options.iter().map(|option| option.name.as_str()).collect(),
+        };
+
+        validator.check_group(entry, &group, &option_names);
+    }
+
+    let json = |name: &str| name == "meta.json" || name == "default_mod.json" || pmp::is_group(name);
+    let mut unreferenced: Vec<&str> = zip.file_names()
+        .filter(|name| !name.ends_with('/') && !json(name))
+        .filter(|name| !referenced.contains(&pmp::normalise(name)))
+        .collect();
+    unreferenced.sort_unstable();
+    for name in unreferenced {
+        validator.push(Severity::Warning, IssueKind::UnreferencedFile, IssueLocation {
+            entry: name.into(),
+            ..Default::default()
+        }, format!("{name} is not used by any option"));
+    }
+
+    let issues = validator.issues;
+    let errors = issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+
+    Ok(ValidationReport {
+        warnings: issues.len() - errors,
+        errors,
+        issues,
+    })
+}
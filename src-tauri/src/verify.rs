@@ -0,0 +1,149 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek};
+
+use blake3::Hasher as Blake3;
+use ttmp::ttmp_extractor::TtmpExtractor;
+use zip::ZipArchive;
+
+use crate::error::Error;
+use crate::pmp;
+
+/// Where a file is used in a modpack: group, option and game path.
+pub type FileKey = (Option<String>, Option<String>, String);
+
+/// Hash some content the same way Heliosphere does.
+pub fn content_hash<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = Blake3::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(data_encoding::BASE64URL_NOPAD.encode(hasher.finalize().as_bytes()))
+}
+
+/// Read back a TexTools modpack and check that it contains exactly the
+/// expected files with the expected contents.
+pub fn verify_ttmp<R: Read + Seek>(reader: R, expected: &HashMap<FileKey, String>) -> anyhow::Result<()> {
+    let extractor = TtmpExtractor::new(reader)
+        .map_err(|e| Error::InvalidArchive(format!("written archive could not be read: {e}")))?;
+
+    let files = extractor.all_files_sorted();
+    let mut zip = extractor.zip().borrow_mut();
+    let mut data = zip.by_name("TTMPD.mpd")
+        .map_err(|_| Error::MissingEntry("TTMPD.mpd".into()))?;
+    let mpd_size = data.size() as usize;
+
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+    let mut staging = tempfile::tempfile()?;
+    let mut last_offset = None;
+    let mut last_hash = String::new();
+
+    for file in files {
+        let key: FileKey = (
+            file.group.map(ToOwned::to_owned),
+            file.option.map(ToOwned::to_owned),
+            file.file.full_path.clone(),
+        );
+        let location = describe(&key);
+
+        if file.file.mod_offset.checked_add(file.file.mod_size).map_or(true, |end| end > mpd_size) {
+            problems.push(format!("{location} lies outside the data file"));
+            continue;
+        }
+
+        // deduplicated files share their data
+        if Some(file.file.mod_offset) != last_offset {
+            last_offset = Some(file.file.mod_offset);
+
+            staging.rewind()?;
+            staging.set_len(0)?;
+            TtmpExtractor::extract_one_into(&file, &mut data, &mut staging)?;
+            staging.rewind()?;
+            last_hash = content_hash(&mut staging)?;
+        }
+
+        match expected.get(&key) {
+            Some(hash) if *hash == last_hash => {}
+            Some(_) => problems.push(format!("{location} differs from the original")),
+            None => problems.push(format!("{location} was not expected")),
+        }
+
+        seen.insert(key);
+    }
+
+    for key in expected.keys() {
+        if !seen.contains(key) {
+            problems.push(format!("{} is missing", describe(key)));
+        }
+    }
+
+    if !problems.is_empty() {
+        problems.sort_unstable();
+        return Err(Error::VerificationFailed(problems).into());
+    }
+
+    Ok(())
+}
+
+/// Read back a Penumbra modpack and check that its options point at exactly
+/// the expected files with the expected contents.
+pub fn verify_pmp<R: Read + Seek>(reader: R, expected: &HashMap<FileKey, String>) -> anyhow::Result<()> {
+    let mut zip = ZipArchive::new(reader)
+        .map_err(|e| Error::InvalidArchive(format!("written archive could not be read: {e}")))?;
+    let mut jsons = pmp::read_jsons(&mut zip)?;
+    let entries = pmp::entry_names(&zip);
+
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+    let mut hashes: HashMap<String, String> = HashMap::new();
+
+    for map in pmp::files_maps(&mut jsons) {
+        for (game_path, local) in map.files.iter() {
+            let key: FileKey = (map.group.clone(), map.option.clone(), game_path.clone());
+            let location = describe(&key);
+
+            let entry = match local.as_str().and_then(|local| entries.get(&pmp::normalise(local))) {
+                Some(entry) => entry,
+                None => {
+                    problems.push(format!("{location} points at a missing file"));
+                    continue;
+                }
+            };
+
+            let hash = match hashes.get(entry) {
+                Some(hash) => hash.clone(),
+                None => {
+                    let hash = content_hash(zip.by_name(entry)?)?;
+                    hashes.insert(entry.clone(), hash.clone());
+                    hash
+                }
+            };
+
+            match expected.get(&key) {
+                Some(expected) if *expected == hash => {}
+                Some(_) => problems.push(format!("{location} differs from the original")),
+                None => problems.push(format!("{location} was not expected")),
+            }
+
+            seen.insert(key);
+        }
+    }
+
+    for key in expected.keys() {
+        if !seen.contains(key) {
+            problems.push(format!("{} is missing", describe(key)));
+        }
+    }
+
+    if !problems.is_empty() {
+        problems.sort_unstable();
+        return Err(Error::VerificationFailed(problems).into());
+    }
+
+    Ok(())
+}
+
+fn describe(key: &FileKey) -> String {
+    match key {
+        (Some(group), Some(option), path) => format!("{path} in {group} / {option}"),
+        (_, _, path) => path.clone(),
+    }
+}